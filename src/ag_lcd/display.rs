@@ -604,8 +604,7 @@ where
         pos += self.offsets[row as usize];
         self.command(Command::SetDDRAMAddr as u8 | pos);
 
-        Delay::wait_for(4).await;
-        // self.delay.delay_us(CMD_DELAY);
+        Delay::wait_for_us(CMD_DELAY as u32).await;
     }
 
     /// Scroll the display right or left.
@@ -1087,8 +1086,7 @@ where
     }
 
     pub async fn write_async(&mut self, value: u8) {
-        Delay::wait_for(1).await;
-        // self.delay.delay_us(CHR_DELAY);
+        Delay::wait_for_us(CHR_DELAY as u32).await;
         self.send(value, true);
     }
 
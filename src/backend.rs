@@ -0,0 +1,15 @@
+//! The few platform primitives the executor and the timers are built on.
+//!
+//! On the AVR they map to the hardware. Everywhere else a host implementation
+//! is used, which runs on a virtual clock so the async code can be exercised
+//! deterministically on a development machine.
+
+#[cfg(target_arch = "avr")]
+mod avr;
+#[cfg(target_arch = "avr")]
+pub use avr::*;
+
+#[cfg(not(target_arch = "avr"))]
+mod host;
+#[cfg(not(target_arch = "avr"))]
+pub use host::*;
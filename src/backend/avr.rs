@@ -0,0 +1,32 @@
+pub use avr_device::interrupt::{free, CriticalSection, Mutex};
+
+use avr_device::atmega328p::CPU;
+
+use crate::sleep::SleepMode;
+
+/// Called by the executor when no task is ready. `select` runs with
+/// interrupts disabled and returns the sleep mode, or `None` if a task was
+/// woken meanwhile. Sleeps until the next interrupt.
+pub fn idle(select: impl FnOnce() -> Option<SleepMode>) {
+    avr_device::interrupt::disable();
+    let Some(mode) = select() else {
+        unsafe { avr_device::interrupt::enable() };
+        return;
+    };
+
+    let cpu = unsafe { &*CPU::ptr() };
+    cpu.smcr.write(|w| {
+        match mode {
+            SleepMode::Idle => w.sm().idle(),
+            SleepMode::PowerSave => w.sm().psave(),
+            SleepMode::PowerDown => w.sm().pdown(),
+        }
+        .se()
+        .set_bit()
+    });
+    // The instruction after `sei` is executed before any pending interrupt,
+    // so a wakeup cannot slip in between the check and the sleep.
+    unsafe { avr_device::interrupt::enable() };
+    avr_device::asm::sleep();
+    cpu.smcr.write(|w| w.se().clear_bit());
+}
@@ -0,0 +1,75 @@
+extern crate std;
+
+use core::{cell::Cell, cell::UnsafeCell, marker::PhantomData};
+
+use crate::{sleep::SleepMode, timers::virtual_clock};
+
+static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+std::thread_local! {
+    static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Token proving that the global lock is held, mirroring
+/// `avr_device::interrupt::CriticalSection`.
+#[derive(Clone, Copy)]
+pub struct CriticalSection<'cs> {
+    _lifetime: PhantomData<&'cs ()>,
+}
+
+/// Mirrors `avr_device::interrupt::Mutex`.
+pub struct Mutex<T> {
+    inner: UnsafeCell<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn borrow<'cs>(&'cs self, _cs: CriticalSection<'cs>) -> &'cs T {
+        unsafe { &*self.inner.get() }
+    }
+}
+
+// The contents are only reachable while the global lock is held.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+struct Nested;
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        NESTING.with(|n| n.set(n.get() - 1));
+    }
+}
+
+/// Runs `f` while holding a global, reentrant lock. This stands in for
+/// disabling interrupts.
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(CriticalSection<'_>) -> R,
+{
+    let _guard = match NESTING.with(|n| n.get()) {
+        0 => Some(LOCK.lock().unwrap_or_else(|e| e.into_inner())),
+        _ => None,
+    };
+    NESTING.with(|n| n.set(n.get() + 1));
+    let _nested = Nested;
+    f(CriticalSection {
+        _lifetime: PhantomData,
+    })
+}
+
+/// Called by the executor when no task is ready. Jumps the virtual clock to
+/// the next timer deadline, unless `select` reports that a task was woken
+/// meanwhile. The sleep mode itself does not matter on the host.
+pub fn idle(select: impl FnOnce() -> Option<SleepMode>) {
+    if select().is_none() {
+        return;
+    }
+    if !virtual_clock::advance_to_next_deadline() {
+        panic!("all tasks are waiting, but no timer is pending");
+    }
+}
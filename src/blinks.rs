@@ -1,44 +1,95 @@
-use crate::futures::delay::Delay;
+use crate::{futures::delay::Delay, time::Duration};
+#[cfg(target_arch = "avr")]
+use crate::futures::ticker::{MissedTickPolicy, Ticker};
 
-use arduino_hal::port::mode::{Output, PwmOutput};
-use arduino_hal::port::{Pin, PinOps};
+#[cfg(target_arch = "avr")]
+use arduino_hal::port::mode::PwmOutput;
+#[cfg(target_arch = "avr")]
+use arduino_hal::port::Pin;
+#[cfg(target_arch = "avr")]
 use arduino_hal::simple_pwm::{PwmPinOps, Timer1Pwm};
+use embedded_hal::digital::v2::OutputPin;
 
-const MORSE_UNIT: u32 = 250;
+const MORSE_UNIT: Duration = Duration::from_millis(250);
 
-async fn blink<X>(led: &mut Pin<Output, X>, factor: u8)
+async fn blink<P>(led: &mut P, factor: u8)
 where
-    X: PinOps,
+    P: OutputPin,
 {
-    led.set_high();
+    led.set_high().ok();
     Delay::wait_for(MORSE_UNIT * factor as u32).await;
-    led.set_low();
+    led.set_low().ok();
     Delay::wait_for(MORSE_UNIT).await;
 }
 
 const SOS_BLINKS: [u8; 9] = [1, 1, 1, 3, 3, 3, 1, 1, 1];
 
-pub async fn sos<X>(led: &mut Pin<Output, X>)
+pub async fn sos<P>(led: &mut P)
 where
-    X: PinOps,
+    P: OutputPin,
 {
     loop {
         for factor in SOS_BLINKS.iter() {
             blink(led, *factor).await;
         }
-        Delay::wait_for(6 * MORSE_UNIT).await;
+        Delay::wait_for(MORSE_UNIT * 6).await;
     }
 }
 
+#[cfg(target_arch = "avr")]
 pub async fn pulse<X>(led: &mut Pin<PwmOutput<Timer1Pwm>, X>)
 where
     X: PwmPinOps<Timer1Pwm>,
 {
     led.enable();
+    let mut ticker = Ticker::every(Duration::from_millis(10)).with_policy(MissedTickPolicy::Skip);
     loop {
         for x in (0..=255).chain((1..=254).rev()) {
             led.set_duty(x);
-            Delay::wait_for(10).await;
+            ticker.next().await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        futures::timeout::with_timeout,
+        simulation::{block_on, RecordingPin},
+        timers::virtual_clock,
+    };
+
+    #[test]
+    fn sos_timing() {
+        let _clock = virtual_clock::lock_for_test();
+        let mut led = RecordingPin::default();
+        let _ = block_on(with_timeout(Duration::from_millis(7600), sos(&mut led)));
+        let expected = [
+            // S
+            (0, true),
+            (250, false),
+            (500, true),
+            (750, false),
+            (1000, true),
+            (1250, false),
+            // O
+            (1500, true),
+            (2250, false),
+            (2500, true),
+            (3250, false),
+            (3500, true),
+            (4250, false),
+            // S
+            (4500, true),
+            (4750, false),
+            (5000, true),
+            (5250, false),
+            (5500, true),
+            (5750, false),
+            // seven units between the words
+            (7500, true),
+        ];
+        assert_eq!(led.changes, expected);
+    }
+}
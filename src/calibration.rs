@@ -0,0 +1,223 @@
+//! Calibration of the CPU clock against the 1PPS output of a GPS receiver on
+//! INT0 (d2).
+//!
+//! [`calibrate`] counts Timer0 ticks between the first and the last of a
+//! number of pulses, which is accurate to about one tick over the whole run.
+//! The resulting clock error corrects `millis` from then on and is stored in
+//! the EEPROM, so that [`load`] applies it again after a reset.
+
+use core::cell::Cell;
+
+use avr_device::interrupt::Mutex;
+
+use crate::{
+    config::{CPU_FREQUENCY, TIMER0_PRESCALER},
+    dbgprint,
+    futures::{signal::Signal, timeout::with_timeout},
+    sleep,
+    time::Duration,
+    timers::{clock_error_ppb, set_clock_error_ppb, wide_ticks, MAX_CLOCK_ERROR_PPB},
+};
+
+/// Length of a calibration run if none is given
+pub const DEFAULT_SECONDS: u16 = 60;
+/// Longest calibration run, which keeps the arithmetic in range
+pub const MAX_SECONDS: u16 = 3600;
+
+/// Time without a pulse after which the calibration gives up
+const PULSE_TIMEOUT: Duration = Duration::from_millis(2500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// No pulse arrived for [`PULSE_TIMEOUT`]
+    NoSignal,
+    /// The clock is off by more than [`MAX_CLOCK_ERROR_PPB`]
+    OutOfRange,
+}
+
+#[derive(Clone, Copy)]
+struct Measurement {
+    first: u64,
+    last: u64,
+    pulses: u16,
+}
+
+/// The running measurement, `None` outside of [`calibrate`]
+static MEASUREMENT: Mutex<Cell<Option<Measurement>>> = Mutex::new(Cell::new(None));
+/// The measurement after the latest pulse
+static PULSE: Signal<Measurement> = Signal::new();
+
+#[avr_device::interrupt(atmega328p)]
+fn INT0() {
+    let now = wide_ticks();
+    avr_device::interrupt::free(|cs| {
+        let cell = MEASUREMENT.borrow(cs);
+        let Some(mut measurement) = cell.get() else {
+            return;
+        };
+        let interval = (now - measurement.last) * TIMER0_PRESCALER as u64;
+        let nominal = CPU_FREQUENCY as u64;
+        // Glitches and missed pulses start the measurement over.
+        if measurement.pulses == 0 || interval.abs_diff(nominal) > nominal / 100 {
+            measurement = Measurement {
+                first: now,
+                last: now,
+                pulses: 1,
+            };
+        } else {
+            measurement.last = now;
+            measurement.pulses += 1;
+        }
+        cell.set(Some(measurement));
+        PULSE.signal(measurement);
+    });
+}
+
+/// Measures the clock error over `seconds` intervals between pulses, up to
+/// [`MAX_SECONDS`], then applies and stores it. Returns the error in parts
+/// per billion, positive if the clock runs fast.
+pub async fn calibrate(seconds: u16) -> Result<i32, CalibrationError> {
+    let seconds = seconds.clamp(1, MAX_SECONDS);
+    // INT0 only detects edges in Idle.
+    let _awake = sleep::stay_awake();
+    avr_device::interrupt::free(|cs| {
+        MEASUREMENT.borrow(cs).set(Some(Measurement {
+            first: 0,
+            last: 0,
+            pulses: 0,
+        }))
+    });
+    PULSE.reset();
+    let result = loop {
+        match with_timeout(PULSE_TIMEOUT, PULSE.wait()).await {
+            Err(_) => break Err(CalibrationError::NoSignal),
+            Ok(measurement) if measurement.pulses > seconds => break Ok(measurement),
+            Ok(_) => {}
+        }
+    };
+    avr_device::interrupt::free(|cs| MEASUREMENT.borrow(cs).set(None));
+
+    let measurement = result?;
+    let measured = ((measurement.last - measurement.first) * TIMER0_PRESCALER as u64) as i64;
+    let nominal = (measurement.pulses - 1) as i64 * CPU_FREQUENCY as i64;
+    let ppb = (measured - nominal) * 1_000_000_000 / nominal;
+    if ppb.abs() > MAX_CLOCK_ERROR_PPB as i64 {
+        return Err(CalibrationError::OutOfRange);
+    }
+    set_clock_error_ppb(ppb as i32);
+    eeprom::store(ppb as i32);
+    Ok(ppb as i32)
+}
+
+/// Applies the clock error stored by the last calibration, if any.
+pub fn load() {
+    if let Some(ppb) = eeprom::load() {
+        set_clock_error_ppb(ppb);
+        dbgprint!("clock error {} ppb", clock_error_ppb());
+    }
+}
+
+/// Handles the console commands `calibrate`, `calibrate <seconds>` and
+/// `calibrate off`.
+pub async fn command(line: &str) {
+    let arg = line.trim().strip_prefix("calibrate").unwrap_or("").trim();
+    let seconds = match arg {
+        "" => DEFAULT_SECONDS,
+        "off" => {
+            set_clock_error_ppb(0);
+            eeprom::store(0);
+            dbgprint!("clock error 0 ppb");
+            return;
+        }
+        arg => match arg.parse() {
+            Ok(seconds) => seconds,
+            Err(_) => {
+                dbgprint!("expected the number of seconds");
+                return;
+            }
+        },
+    };
+    dbgprint!("calibrating for {} s", seconds.clamp(1, MAX_SECONDS));
+    match calibrate(seconds).await {
+        Ok(ppb) => dbgprint!("clock error {} ppb", ppb),
+        Err(CalibrationError::NoSignal) => dbgprint!("no 1PPS signal on d2"),
+        Err(CalibrationError::OutOfRange) => dbgprint!("clock error out of range"),
+    }
+}
+
+/// Storage of the clock error in the EEPROM.
+///
+/// The EE_READY interrupt drives the [`InterruptExecutor`], so the EERIE bit
+/// is left alone. Its tasks are delayed while a byte is written, which takes
+/// 3.4 ms.
+///
+/// [`InterruptExecutor`]: crate::executor::InterruptExecutor
+mod eeprom {
+    const EEPE: u8 = 1 << 1;
+    const EEMPE: u8 = 1 << 2;
+    const EERIE: u8 = 1 << 3;
+
+    const ADDRESS: u16 = 0;
+    const MAGIC: u16 = 0xca1b;
+
+    fn eeprom() -> &'static arduino_hal::pac::EEPROM {
+        unsafe { &*arduino_hal::pac::EEPROM::ptr() }
+    }
+
+    fn wait_for_write() {
+        while eeprom().eecr.read().eepe().bit_is_set() {}
+    }
+
+    fn read_byte(address: u16) -> u8 {
+        wait_for_write();
+        avr_device::interrupt::free(|_| {
+            let eeprom = eeprom();
+            eeprom.eear.write(|w| unsafe { w.bits(address) });
+            eeprom.eecr.modify(|_, w| w.eere().set_bit());
+            eeprom.eedr.read().bits()
+        })
+    }
+
+    fn write_byte(address: u16, value: u8) {
+        // Saves wear on the cells
+        if read_byte(address) == value {
+            return;
+        }
+        wait_for_write();
+        avr_device::interrupt::free(|_| {
+            let eeprom = eeprom();
+            eeprom.eear.write(|w| unsafe { w.bits(address) });
+            eeprom.eedr.write(|w| unsafe { w.bits(value) });
+            // EEPE has to follow EEMPE within four clock cycles, so both
+            // values are computed in advance. EEPM = 0 erases and writes.
+            let eerie = eeprom.eecr.read().bits() & EERIE;
+            let master_enable = eerie | EEMPE;
+            let enable = eerie | EEMPE | EEPE;
+            eeprom.eecr.write(|w| unsafe { w.bits(master_enable) });
+            eeprom.eecr.write(|w| unsafe { w.bits(enable) });
+        });
+    }
+
+    fn read_bytes<const N: usize>(address: u16) -> [u8; N] {
+        core::array::from_fn(|i| read_byte(address + i as u16))
+    }
+
+    fn write_bytes(address: u16, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            write_byte(address + i as u16, byte);
+        }
+    }
+
+    pub fn load() -> Option<i32> {
+        let magic = u16::from_le_bytes(read_bytes(ADDRESS));
+        (magic == MAGIC).then(|| i32::from_le_bytes(read_bytes(ADDRESS + 2)))
+    }
+
+    /// The magic number is written last, so that a reset in between leaves
+    /// no half-written value behind.
+    pub fn store(ppb: i32) {
+        write_bytes(ADDRESS, &[0xff, 0xff]);
+        write_bytes(ADDRESS + 2, &ppb.to_le_bytes());
+        write_bytes(ADDRESS, &MAGIC.to_le_bytes());
+    }
+}
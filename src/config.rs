@@ -0,0 +1,29 @@
+//! Compile-time configuration of the runtime.
+
+/// Clock frequency of the CPU in Hz, 16 MHz unless the `cpu-8mhz` or
+/// `cpu-20mhz` feature is enabled. The board feature of `arduino-hal` has to
+/// match, e.g. `sparkfun-promini-3v3` for an 8 MHz Pro Mini.
+#[cfg(not(any(feature = "cpu-8mhz", feature = "cpu-20mhz")))]
+pub const CPU_FREQUENCY: u32 = 16_000_000;
+#[cfg(feature = "cpu-8mhz")]
+pub const CPU_FREQUENCY: u32 = 8_000_000;
+#[cfg(feature = "cpu-20mhz")]
+pub const CPU_FREQUENCY: u32 = 20_000_000;
+
+// The delays of arduino-hal use the clock of its board feature.
+#[cfg(target_arch = "avr")]
+const _: () = assert!(
+    CPU_FREQUENCY == <arduino_hal::DefaultClock as arduino_hal::clock::Clock>::FREQ,
+    "CPU_FREQUENCY does not match the board feature of arduino-hal"
+);
+
+#[cfg(all(feature = "cpu-8mhz", feature = "cpu-20mhz"))]
+compile_error!("the features `cpu-8mhz` and `cpu-20mhz` are mutually exclusive");
+
+/// Prescaler of Timer0, which drives `millis`. Must be 8, 64, 256 or 1024.
+/// `millis` stays exact for any prescaler, but the resolution of `micros`
+/// and of the timers in tickless mode is one Timer0 tick.
+#[cfg(not(feature = "tickless"))]
+pub const TIMER0_PRESCALER: u32 = 64;
+#[cfg(feature = "tickless")]
+pub const TIMER0_PRESCALER: u32 = 1024;
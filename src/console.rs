@@ -0,0 +1,61 @@
+//! Line-based command console on the serial port.
+//!
+//! The receive interrupt collects a line until `\r` or `\n`, and [`read_line`]
+//! hands it to the waiting task. Bytes that arrive while the previous line was
+//! not picked up yet are dropped.
+
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use avr_device::interrupt::Mutex;
+use heapless::{String, Vec};
+
+use crate::{futures::atomic_waker::AtomicWaker, sleep};
+
+/// Longest command, longer lines are cut off
+pub const MAX_LINE_LENGTH: usize = 32;
+
+static LINE: Mutex<RefCell<Vec<u8, MAX_LINE_LENGTH>>> = Mutex::new(RefCell::new(Vec::new()));
+static LINE_READY: AtomicBool = AtomicBool::new(false);
+static LINE_WAKER: AtomicWaker = AtomicWaker::new();
+
+#[avr_device::interrupt(atmega328p)]
+fn USART_RX() {
+    let usart = unsafe { &*arduino_hal::pac::USART0::ptr() };
+    let byte = usart.udr0.read().bits();
+    if LINE_READY.load(Ordering::Acquire) {
+        return;
+    }
+    avr_device::interrupt::free(|cs| {
+        let mut line = LINE.borrow(cs).borrow_mut();
+        match byte {
+            b'\r' | b'\n' if line.is_empty() => {}
+            b'\r' | b'\n' => {
+                LINE_READY.store(true, Ordering::Release);
+                LINE_WAKER.wake();
+            }
+            _ => {
+                let _ = line.push(byte);
+            }
+        }
+    })
+}
+
+/// Waits for the next non-empty line. Invalid UTF-8 is dropped.
+pub async fn read_line() -> String<MAX_LINE_LENGTH> {
+    // Received bytes only wake the board from Idle.
+    let _awake = sleep::stay_awake();
+    loop {
+        LINE_WAKER.wait_until(|| LINE_READY.load(Ordering::Acquire)).await;
+        let line = avr_device::interrupt::free(|cs| {
+            let line = LINE.borrow(cs).replace(Vec::new());
+            LINE_READY.store(false, Ordering::Release);
+            line
+        });
+        if let Ok(line) = String::from_utf8(line) {
+            return line;
+        }
+    }
+}
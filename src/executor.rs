@@ -2,16 +2,50 @@
 // https://github.com/rust-embedded-community/async-on-embedded/blob/master/async-embedded/src/executor.rs
 
 use core::{
+    cell::{Cell, UnsafeCell},
     future::Future,
+    marker::{PhantomData, PhantomPinned},
+    mem::{self, MaybeUninit},
+    pin::Pin,
+    ptr,
     sync::atomic::{AtomicBool, Ordering},
     task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
 };
 
 use pin_utils::pin_mut;
 
-pub struct Executor {}
+use crate::{
+    backend::{self, Mutex},
+    dbgprint,
+    sleep::{self, SleepMode},
+    timers::{ticks, ticks_to_micros},
+    watchdog,
+};
+
+/// Number of bytes reserved for the future of every spawned task
+pub const TASK_SIZE: usize = 192;
+
+struct TaskHeader {
+    ready: AtomicBool,
+    /// Makes sure the executor owning the task gets to run after a wakeup
+    pend: fn(),
+}
+
+impl TaskHeader {
+    const fn new(pend: fn()) -> Self {
+        Self {
+            ready: AtomicBool::new(false),
+            pend,
+        }
+    }
+}
+
+/// Pender of the thread-mode executor, which re-checks its tasks after every
+/// interrupt anyway
+fn no_pend() {}
 
-// NOTE `*const ()` is &AtomicBool
+// NOTE `*const ()` is &TaskHeader, which may be gone together with its
+// executor, so it is only dereferenced if the executor is in `EXECUTORS`
 static VTABLE: RawWakerVTable = {
     unsafe fn clone(p: *const ()) -> RawWaker {
         RawWaker::new(p, &VTABLE)
@@ -20,7 +54,15 @@ static VTABLE: RawWakerVTable = {
         wake_by_ref(p)
     }
     unsafe fn wake_by_ref(p: *const ()) {
-        (*(p as *const AtomicBool)).store(true, Ordering::Release)
+        let header = p as *const TaskHeader;
+        backend::free(|cs| {
+            if !EXECUTORS.borrow(cs).contains(header) {
+                return;
+            }
+            let header = &*header;
+            header.ready.store(true, Ordering::Release);
+            (header.pend)();
+        });
     }
     unsafe fn drop(_: *const ()) {
         // no-op
@@ -29,34 +71,828 @@ static VTABLE: RawWakerVTable = {
     RawWakerVTable::new(clone, wake, wake_by_ref, drop)
 };
 
-impl Executor {
-    pub fn new() -> Self {
-        Self {}
+fn waker_for(header: &TaskHeader) -> Waker {
+    unsafe { Waker::from_raw(RawWaker::new(header as *const _ as *const _, &VTABLE)) }
+}
+
+/// Task headers of an executor, linked into [`EXECUTORS`] while wakers may
+/// reach them.
+///
+/// Wakers do not keep their task alive, they can still be stored somewhere
+/// when the executor is dropped or [`Executor::block_on`] has returned. Waking
+/// them then does nothing, or wakes a task that happens to have its header at
+/// the same address, which is a spurious wakeup. All fields are only accessed
+/// in a critical section.
+struct ExecutorLink {
+    /// Header of the future passed to [`Executor::block_on`], or null
+    top_level: Cell<*const TaskHeader>,
+    slots: Cell<*const [TaskSlot]>,
+    next: Cell<*const ExecutorLink>,
+    linked: Cell<bool>,
+}
+
+impl ExecutorLink {
+    const fn new() -> Self {
+        Self {
+            top_level: Cell::new(ptr::null()),
+            slots: Cell::new(ptr::slice_from_raw_parts(ptr::null(), 0)),
+            next: Cell::new(ptr::null()),
+            linked: Cell::new(false),
+        }
+    }
+
+    fn owns(&self, header: *const TaskHeader) -> bool {
+        let slots = unsafe { &*self.slots.get() };
+        ptr::eq(self.top_level.get(), header) || slots.iter().any(|slot| ptr::eq(&slot.header, header))
+    }
+}
+
+/// Singly linked list of the executors whose tasks can be woken
+struct ExecutorList {
+    head: Cell<*const ExecutorLink>,
+}
+
+// The links are only reached through the list while the critical section is
+// held.
+unsafe impl Send for ExecutorList {}
+
+impl ExecutorList {
+    const fn new() -> Self {
+        Self {
+            head: Cell::new(ptr::null()),
+        }
     }
 
-    pub fn block_on<T: Default>(&self, f: impl Future<Output = T>) -> T {
+    /// Links the headers of an executor, unless they are linked already.
+    ///
+    /// # Safety
+    ///
+    /// `link`, `slots` and `top_level` must stay at their addresses until
+    /// `link` is removed again.
+    unsafe fn insert(&self, link: &ExecutorLink, slots: &[TaskSlot], top_level: *const TaskHeader) {
+        if link.linked.get() {
+            return;
+        }
+        link.top_level.set(top_level);
+        link.slots.set(slots);
+        link.next.set(self.head.get());
+        self.head.set(link);
+        link.linked.set(true);
+    }
+
+    fn remove(&self, link: &ExecutorLink) {
+        if !link.linked.get() {
+            return;
+        }
+        let mut prev = &self.head;
+        while let Some(next) = unsafe { prev.get().as_ref() } {
+            if ptr::eq(next, link) {
+                prev.set(link.next.get());
+                link.linked.set(false);
+                return;
+            }
+            prev = &next.next;
+        }
+    }
+
+    fn contains(&self, header: *const TaskHeader) -> bool {
+        let mut link = self.head.get();
+        while let Some(l) = unsafe { link.as_ref() } {
+            if l.owns(header) {
+                return true;
+            }
+            link = l.next.get();
+        }
+        false
+    }
+}
+
+/// Executors that are running or still have tasks, see [`ExecutorLink`]
+static EXECUTORS: Mutex<ExecutorList> = Mutex::new(ExecutorList::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// All task slots are in use
+    NoFreeSlot,
+}
+
+/// Identifies a task by the slot it occupies. Tasks of the
+/// [`InterruptExecutor`] are numbered from [`INTERRUPT_TASK_IDS`] on, so IDs
+/// are unique across both executors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskId(pub u8);
+
+/// First [`TaskId`] of the [`InterruptExecutor`]
+pub const INTERRUPT_TASK_IDS: u8 = 128;
+
+/// Task that is being polled right now, if any
+static CURRENT_TASK: Mutex<Cell<Option<TaskId>>> = Mutex::new(Cell::new(None));
+
+/// Returns the task that is being polled, `None` outside of a task.
+pub fn current_task() -> Option<TaskId> {
+    backend::free(|cs| CURRENT_TASK.borrow(cs).get())
+}
+
+/// Runtime statistics of a task, measured in Timer0 ticks
+#[derive(Debug, Clone, Copy)]
+pub struct TaskStats {
+    /// Number of times the task was polled
+    pub polls: u32,
+    /// Total time spent inside `poll`
+    pub busy_ticks: u32,
+    /// Longest single `poll`
+    pub max_poll_ticks: u32,
+}
+
+impl TaskStats {
+    const fn new() -> Self {
+        Self {
+            polls: 0,
+            busy_ticks: 0,
+            max_poll_ticks: 0,
+        }
+    }
+
+    fn record_poll(&mut self, ticks: u32) {
+        self.polls = self.polls.saturating_add(1);
+        self.busy_ticks = self.busy_ticks.saturating_add(ticks);
+        self.max_poll_ticks = self.max_poll_ticks.max(ticks);
+    }
+}
+
+#[repr(C, align(8))]
+struct TaskStorage([MaybeUninit<u8>; TASK_SIZE]);
+
+/// Fails the build if `F` does not fit into a task slot. Evaluated when a
+/// task of type `F` is spawned.
+struct AssertFits<F>(PhantomData<F>);
+
+impl<F> AssertFits<F> {
+    const OK: () = assert!(
+        mem::size_of::<F>() <= TASK_SIZE && mem::align_of::<F>() <= mem::align_of::<TaskStorage>(),
+        "the future of the task is larger than TASK_SIZE"
+    );
+}
+
+/// Type-erased functions to poll and drop the future stored in a slot
+#[derive(Clone, Copy)]
+struct TaskFns {
+    poll: unsafe fn(*mut u8, &mut Context<'_>) -> Poll<()>,
+    drop: unsafe fn(*mut u8),
+}
+
+unsafe fn poll_task<F: Future<Output = ()>>(p: *mut u8, cx: &mut Context<'_>) -> Poll<()> {
+    Pin::new_unchecked(&mut *(p as *mut F)).poll(cx)
+}
+
+unsafe fn drop_task<F>(p: *mut u8) {
+    ptr::drop_in_place(p as *mut F)
+}
+
+struct TaskSlot {
+    header: TaskHeader,
+    /// `None` while the slot is free
+    fns: Cell<Option<TaskFns>>,
+    storage: UnsafeCell<TaskStorage>,
+    stats: Cell<TaskStats>,
+    /// Incremented on every spawn, so stale handles cannot abort a new task.
+    /// It wraps around after 65536 spawns into the slot, a handle that is
+    /// kept that long could abort the task that is running then.
+    generation: Mutex<Cell<u16>>,
+    /// The future is dropped instead of polled on the next wakeup
+    abort: AtomicBool,
+    /// Task awaiting the [`JoinHandle`]
+    join_waker: Mutex<Cell<Option<Waker>>>,
+}
+
+impl TaskSlot {
+    const fn new(pend: fn()) -> Self {
+        Self {
+            header: TaskHeader::new(pend),
+            fns: Cell::new(None),
+            storage: UnsafeCell::new(TaskStorage([MaybeUninit::uninit(); TASK_SIZE])),
+            stats: Cell::new(TaskStats::new()),
+            generation: Mutex::new(Cell::new(0)),
+            abort: AtomicBool::new(false),
+            join_waker: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Polls the task if it was woken up. Returns whether it was polled.
+    fn poll(&self, id: TaskId) -> bool {
+        let Some(fns) = self.fns.get() else {
+            return false;
+        };
+        if !self.header.ready.load(Ordering::Acquire) {
+            return false;
+        }
+        self.header.ready.store(false, Ordering::Release);
+
+        let waker = waker_for(&self.header);
+        if self.abort.load(Ordering::Acquire) {
+            self.finish(fns, id);
+            return true;
+        }
+        let mut cx = Context::from_waker(&waker);
+        let storage = self.storage.get() as *mut u8;
+        // Interrupt tasks preempt thread tasks, so restore the outer task after.
+        let outer = backend::free(|cs| CURRENT_TASK.borrow(cs).replace(Some(id)));
+        let start = ticks();
+        let poll = unsafe { (fns.poll)(storage, &mut cx) };
+        backend::free(|cs| CURRENT_TASK.borrow(cs).set(outer));
+        let mut stats = self.stats.get();
+        stats.record_poll(ticks().wrapping_sub(start));
+        self.stats.set(stats);
+
+        if poll.is_ready() {
+            self.finish(fns, id);
+        }
+        true
+    }
+
+    /// Drops the future and frees the slot.
+    fn finish(&self, fns: TaskFns, id: TaskId) {
+        unsafe { (fns.drop)(self.storage.get() as *mut u8) };
+        // The timers of the task were unlinked when the future was dropped.
+        backend::free(|cs| {
+            self.fns.set(None);
+            watchdog::remove(id, self.generation.borrow(cs).get());
+            if let Some(joiner) = self.join_waker.borrow(cs).take() {
+                joiner.wake();
+            }
+        });
+    }
+
+    fn stats(&self) -> Option<TaskStats> {
+        self.fns.get().map(|_| self.stats.get())
+    }
+}
+
+/// Cancels a task, can be copied freely.
+#[derive(Clone, Copy)]
+pub struct AbortHandle<'e> {
+    slot: &'e TaskSlot,
+    generation: u16,
+}
+
+impl<'e> AbortHandle<'e> {
+    /// Whether the task has completed or was aborted
+    pub fn is_finished(&self) -> bool {
+        backend::free(|cs| {
+            self.slot.fns.get().is_none() || self.slot.generation.borrow(cs).get() != self.generation
+        })
+    }
+
+    /// Cancels the task. Its future is dropped the next time the executor
+    /// runs, together with the timers it is waiting for. Does nothing if the
+    /// task has already finished.
+    pub fn abort(&self) {
+        backend::free(|_| {
+            if !self.is_finished() {
+                self.slot.abort.store(true, Ordering::Release);
+                self.slot.header.ready.store(true, Ordering::Release);
+                (self.slot.header.pend)();
+            }
+        });
+    }
+}
+
+/// Handle to a spawned task. Awaiting it waits until the task has completed
+/// or was aborted. Dropping it detaches the task, which keeps running.
+pub struct JoinHandle<'e> {
+    abort: AbortHandle<'e>,
+    id: TaskId,
+}
+
+impl<'e> JoinHandle<'e> {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Tells the task apart from earlier and later tasks with the same
+    /// [`TaskId`].
+    pub fn generation(&self) -> u16 {
+        self.abort.generation
+    }
+
+    pub fn abort_handle(&self) -> AbortHandle<'e> {
+        self.abort
+    }
+
+    /// See [`AbortHandle::abort`].
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.abort.is_finished()
+    }
+}
+
+impl Future for JoinHandle<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        backend::free(|cs| {
+            if self.is_finished() {
+                Poll::Ready(())
+            } else {
+                self.abort.slot.join_waker.borrow(cs).set(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+        })
+    }
+}
+
+fn print_task_stats(executor: &str, task: TaskId, stats: &TaskStats) {
+    dbgprint!(
+        "{} task {}: {} polls, busy {} us, max poll {} us",
+        executor,
+        task.0,
+        stats.polls,
+        ticks_to_micros(stats.busy_ticks),
+        ticks_to_micros(stats.max_poll_ticks)
+    );
+}
+
+/// # Safety
+///
+/// `future` must outlive the slots, the executors make sure of that with
+/// their lifetime parameter.
+unsafe fn spawn_into<F>(
+    slots: &[TaskSlot],
+    first_id: u8,
+    future: F,
+) -> Result<JoinHandle<'_>, SpawnError>
+where
+    F: Future<Output = ()>,
+{
+    #[allow(clippy::let_unit_value)]
+    let () = AssertFits::<F>::OK;
+    let index = slots
+        .iter()
+        .position(|slot| slot.fns.get().is_none())
+        .ok_or(SpawnError::NoFreeSlot)?;
+    let slot = &slots[index];
+    // The slot is free, so nothing else is referencing its storage.
+    ptr::write(slot.storage.get() as *mut F, future);
+    slot.stats.set(TaskStats::new());
+    let generation = backend::free(|cs| {
+        let generation = slot.generation.borrow(cs);
+        generation.set(generation.get().wrapping_add(1));
+        generation.get()
+    });
+    slot.abort.store(false, Ordering::Release);
+    slot.fns.set(Some(TaskFns {
+        poll: poll_task::<F>,
+        drop: drop_task::<F>,
+    }));
+    slot.header.ready.store(true, Ordering::Release);
+    (slot.header.pend)();
+    Ok(JoinHandle {
+        abort: AbortHandle { slot, generation },
+        id: TaskId(first_id + index as u8),
+    })
+}
+
+/// Polls all woken tasks in `slots`. Returns whether any task was polled.
+fn poll_slots(slots: &[TaskSlot], first_id: u8) -> bool {
+    let mut task_woken = false;
+    for (index, slot) in slots.iter().enumerate() {
+        task_woken |= slot.poll(TaskId(first_id + index as u8));
+    }
+    task_woken
+}
+
+/// Cooperative executor with a fixed-size arena for up to `N` tasks, running
+/// in thread mode from [`Executor::run`].
+///
+/// Its tasks can be preempted by the tasks of an [`InterruptExecutor`].
+///
+/// Tasks are stored inside the executor, so it has to be pinned before tasks
+/// can be spawned. Dropping it drops the tasks that did not complete.
+///
+/// ```
+/// let executor = pin!(Executor::<3>::new());
+/// let executor = executor.into_ref();
+/// executor.spawn(blink(&mut led)).unwrap();
+/// executor.run();
+/// ```
+///
+/// The tasks may borrow data that outlives the executor, but nothing shorter:
+///
+/// ```compile_fail
+/// let executor = pin!(Executor::<1>::new());
+/// let executor = executor.into_ref();
+/// {
+///     let data = [1, 2, 3];
+///     let data = &data;
+///     executor.spawn(async move { black_box(data); }).unwrap();
+/// }
+/// executor.run();
+/// ```
+pub struct Executor<'a, const N: usize> {
+    slots: [TaskSlot; N],
+    /// Of the future passed to [`Executor::block_on`]
+    top_level: TaskHeader,
+    link: ExecutorLink,
+    /// Called once [`Executor::run`] or [`Executor::block_on`] returns
+    shutdown_hook: Option<fn()>,
+    /// Called before sleeping, may change the selected sleep mode
+    idle_hook: Option<fn(SleepMode) -> SleepMode>,
+    /// Invariant, so that `'a` cannot be shortened to a borrow that ends
+    /// before the tasks are run
+    _tasks: PhantomData<fn(&'a ()) -> &'a ()>,
+    _pinned: PhantomPinned,
+}
+
+/// Handle to spawn new tasks onto an [`Executor`], also from within a
+/// running task.
+#[derive(Clone, Copy)]
+pub struct Spawner<'e, 'a> {
+    slots: &'e [TaskSlot],
+    _tasks: PhantomData<fn(&'a ()) -> &'a ()>,
+}
+
+impl<'e, 'a> Spawner<'e, 'a> {
+    pub fn spawn<F>(&self, future: F) -> Result<JoinHandle<'e>, SpawnError>
+    where
+        F: Future<Output = ()> + 'a,
+    {
+        // The executor outlives `'a`, which is invariant.
+        unsafe { spawn_into(self.slots, 0, future) }
+    }
+}
+
+impl<'a, const N: usize> Executor<'a, N> {
+    // Only used to initialize the array, every slot is a separate copy.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY_SLOT: TaskSlot = TaskSlot::new(no_pend);
+
+    pub const fn new() -> Self {
+        Self {
+            slots: [Self::EMPTY_SLOT; N],
+            top_level: TaskHeader::new(no_pend),
+            link: ExecutorLink::new(),
+            shutdown_hook: None,
+            idle_hook: None,
+            _tasks: PhantomData,
+            _pinned: PhantomPinned,
+        }
+    }
+
+    /// Sets a function that puts the hardware into a safe state, e.g. stops
+    /// the motors, once the executor is done.
+    pub const fn with_shutdown_hook(mut self, hook: fn()) -> Self {
+        self.shutdown_hook = Some(hook);
+        self
+    }
+
+    /// Sets a function that is called with the selected sleep mode whenever
+    /// no task is ready, and returns the mode to actually use. It runs with
+    /// interrupts disabled.
+    pub const fn with_idle_hook(mut self, hook: fn(SleepMode) -> SleepMode) -> Self {
+        self.idle_hook = Some(hook);
+        self
+    }
+
+    /// Sleeps until the next interrupt, unless a task or `top_level` was woken
+    /// after the tasks were polled.
+    fn idle(&self, top_level: Option<&TaskHeader>) {
+        backend::idle(|| {
+            let woken = top_level.map_or(false, |header| header.ready.load(Ordering::Acquire))
+                || self.slots.iter().any(|slot| slot.header.ready.load(Ordering::Acquire));
+            if woken {
+                return None;
+            }
+            let mode = sleep::select_mode();
+            Some(self.idle_hook.map_or(mode, |hook| hook(mode)))
+        });
+    }
+
+    /// Makes the wakers of the executor work until it is dropped.
+    fn link(self: Pin<&Self>) {
+        // Pinned, so nothing moves before `drop` unlinks it again.
+        backend::free(|cs| unsafe {
+            EXECUTORS.borrow(cs).insert(&self.link, &self.slots, &self.top_level)
+        });
+    }
+
+    fn shutdown(&self) {
+        if let Some(hook) = self.shutdown_hook {
+            hook();
+        }
+    }
+
+    pub fn spawner(self: Pin<&Self>) -> Spawner<'_, 'a> {
+        Spawner {
+            slots: &self.get_ref().slots,
+            _tasks: PhantomData,
+        }
+    }
+
+    pub fn spawn<F>(self: Pin<&Self>, future: F) -> Result<JoinHandle<'_>, SpawnError>
+    where
+        F: Future<Output = ()> + 'a,
+    {
+        // The executor outlives `'a`, which is invariant.
+        unsafe { spawn_into(&self.get_ref().slots, 0, future) }
+    }
+
+    /// Statistics of a running task, `None` if the task has completed
+    pub fn stats(&self, task: TaskId) -> Option<TaskStats> {
+        self.slots.get(task.0 as usize)?.stats()
+    }
+
+    /// Prints the statistics of all running tasks to the serial console.
+    pub fn print_stats(&self) {
+        for id in 0..N {
+            let task = TaskId(id as u8);
+            if let Some(stats) = self.stats(task) {
+                print_task_stats("thread", task, &stats);
+            }
+        }
+    }
+
+    /// Runs `f` to completion and returns its output. Spawned tasks keep
+    /// running while `f` is pending, but are not waited for.
+    pub fn block_on<T>(self: Pin<&Self>, f: impl Future<Output = T>) -> T {
         pin_mut!(f);
-        let ready = AtomicBool::new(true);
-        let waker =
-            unsafe { Waker::from_raw(RawWaker::new(&ready as *const _ as *const _, &VTABLE)) };
+        self.link();
+        let header = &self.top_level;
+        header.ready.store(true, Ordering::Release);
+        let waker = waker_for(header);
         let val = loop {
             let mut task_woken = false;
-            if ready.load(Ordering::Acquire) {
+            if header.ready.load(Ordering::Acquire) {
                 task_woken = true;
-                ready.store(false, Ordering::Release);
+                header.ready.store(false, Ordering::Release);
 
                 let mut cx = Context::from_waker(&waker);
                 if let Poll::Ready(val) = f.as_mut().poll(&mut cx) {
                     break val;
                 }
             }
+            task_woken |= poll_slots(&self.slots, 0);
+            watchdog::service();
 
             if task_woken {
                 // If at least one task was woken up, do not sleep, try again
                 continue;
             }
-            avr_device::asm::sleep();
+            self.idle(Some(header));
         };
+        self.shutdown();
         val
     }
+
+    /// Runs `f` next to the spawned tasks, for top-level futures that never
+    /// complete.
+    pub fn run_forever(self: Pin<&Self>, f: impl Future<Output = !>) -> ! {
+        self.block_on(f)
+    }
+
+    /// Runs the spawned tasks until all of them have completed.
+    ///
+    /// Every task gets its own wake flag, so a wakeup only causes the task
+    /// that registered the waker to be polled again.
+    #[allow(dead_code)]
+    pub fn run(self: Pin<&Self>) {
+        self.link();
+        loop {
+            let task_woken = poll_slots(&self.slots, 0);
+            watchdog::service();
+
+            if self.slots.iter().all(|slot| slot.fns.get().is_none()) {
+                break;
+            }
+            if task_woken {
+                // If at least one task was woken up, do not sleep, try again
+                continue;
+            }
+            self.idle(None);
+        }
+        self.shutdown();
+    }
+}
+
+// Also tells the drop check that the tasks may use data borrowed for `'a`.
+impl<const N: usize> Drop for Executor<'_, N> {
+    /// Drops the tasks that did not complete, their wakers do nothing from
+    /// now on.
+    fn drop(&mut self) {
+        for (index, slot) in self.slots.iter().enumerate() {
+            if let Some(fns) = slot.fns.get() {
+                slot.finish(fns, TaskId(index as u8));
+            }
+        }
+        backend::free(|cs| EXECUTORS.borrow(cs).remove(&self.link));
+    }
+}
+
+/// Triggers the EE_READY interrupt, which fires as soon as its enable bit is
+/// set while no EEPROM write is in progress.
+#[cfg(target_arch = "avr")]
+fn pend_interrupt_executor() {
+    avr_device::interrupt::free(|_| {
+        let eeprom = unsafe { &*avr_device::atmega328p::EEPROM::ptr() };
+        eeprom.eecr.modify(|_, w| w.eerie().set_bit());
+    });
+}
+
+/// Executor for high-priority tasks, polled from the EE_READY interrupt.
+///
+/// Whenever one of its tasks is woken, the interrupt is triggered and preempts
+/// the thread-mode [`Executor`], e.g. a task blocked in a long I2C transfer.
+/// Interrupts stay enabled while its tasks are polled, so the timers keep
+/// running.
+///
+/// It has to be placed in a `static` and hooked up to the interrupt:
+///
+/// ```
+/// static HIGH_PRIORITY: InterruptExecutor<2> = InterruptExecutor::new();
+///
+/// #[avr_device::interrupt(atmega328p)]
+/// fn EE_READY() {
+///     unsafe { HIGH_PRIORITY.on_interrupt() }
+/// }
+/// ```
+#[cfg(target_arch = "avr")]
+pub struct InterruptExecutor<const N: usize> {
+    slots: [TaskSlot; N],
+    link: ExecutorLink,
+    running: AtomicBool,
+}
+
+// The slots are only polled from the EE_READY interrupt and only filled
+// inside a critical section.
+#[cfg(target_arch = "avr")]
+unsafe impl<const N: usize> Sync for InterruptExecutor<N> {}
+
+#[cfg(target_arch = "avr")]
+impl<const N: usize> InterruptExecutor<N> {
+    // Only used to initialize the array, every slot is a separate copy.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY_SLOT: TaskSlot = TaskSlot::new(pend_interrupt_executor);
+
+    pub const fn new() -> Self {
+        Self {
+            slots: [Self::EMPTY_SLOT; N],
+            link: ExecutorLink::new(),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    pub fn spawn<F>(&'static self, future: F) -> Result<JoinHandle<'static>, SpawnError>
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        // The executor and the future are `'static`.
+        backend::free(|cs| unsafe {
+            EXECUTORS.borrow(cs).insert(&self.link, &self.slots, ptr::null());
+            spawn_into(&self.slots, INTERRUPT_TASK_IDS, future)
+        })
+    }
+
+    /// Statistics of a running task, `None` if the task has completed
+    pub fn stats(&self, task: TaskId) -> Option<TaskStats> {
+        let index = task.0.checked_sub(INTERRUPT_TASK_IDS)?;
+        avr_device::interrupt::free(|_| self.slots.get(index as usize)?.stats())
+    }
+
+    /// Prints the statistics of all running tasks to the serial console.
+    pub fn print_stats(&self) {
+        for id in 0..N {
+            let task = TaskId(INTERRUPT_TASK_IDS + id as u8);
+            if let Some(stats) = self.stats(task) {
+                print_task_stats("interrupt", task, &stats);
+            }
+        }
+    }
+
+    /// Polls all woken tasks. Must only be called from the EE_READY interrupt
+    /// handler.
+    pub unsafe fn on_interrupt(&self) {
+        let eeprom = &*avr_device::atmega328p::EEPROM::ptr();
+        eeprom.eecr.modify(|_, w| w.eerie().clear_bit());
+        if self.running.load(Ordering::Acquire) {
+            // Nested trigger, the outer invocation picks up the woken task.
+            return;
+        }
+        self.running.store(true, Ordering::Release);
+        avr_device::interrupt::enable();
+
+        loop {
+            if poll_slots(&self.slots, INTERRUPT_TASK_IDS) {
+                continue;
+            }
+
+            // Wakeups after this check trigger the interrupt again.
+            let idle = avr_device::interrupt::free(|_| {
+                let idle = !self
+                    .slots
+                    .iter()
+                    .any(|slot| slot.header.ready.load(Ordering::Acquire));
+                if idle {
+                    self.running.store(false, Ordering::Release);
+                }
+                idle
+            });
+            if idle {
+                break;
+            }
+        }
+
+        avr_device::interrupt::disable();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, future::poll_fn, pin::pin};
+
+    use super::*;
+    use crate::{
+        futures::delay::Delay,
+        time::{Duration, Instant},
+        timers::{millis, virtual_clock, WAKERS},
+    };
+
+    fn next_wake_time() -> Option<Instant> {
+        backend::free(|cs| WAKERS.borrow(cs).borrow().next_wake_time())
+    }
+
+    #[test]
+    fn join_waits_until_the_task_completes() {
+        let _clock = virtual_clock::lock_for_test();
+        let done = Cell::new(false);
+        let executor = pin!(Executor::<1>::new());
+        let executor = executor.into_ref();
+        let task = executor
+            .spawn(async {
+                Delay::wait_for(Duration::from_millis(20)).await;
+                done.set(true);
+            })
+            .unwrap();
+        executor.block_on(task);
+        assert!(done.get());
+        assert_eq!(millis(), 20);
+    }
+
+    #[test]
+    fn aborting_a_task_drops_its_timers() {
+        let _clock = virtual_clock::lock_for_test();
+        let executor = pin!(Executor::<1>::new());
+        let executor = executor.into_ref();
+        let task = executor.spawn(Delay::wait_for(Duration::from_secs(1))).unwrap();
+        let handle = task.abort_handle();
+        executor.block_on(async {
+            // Gives the task a chance to start waiting.
+            Delay::wait_for(Duration::from_millis(10)).await;
+            assert_eq!(next_wake_time(), Some(Instant::from_millis(1000)));
+            handle.abort();
+            task.await;
+        });
+        assert!(handle.is_finished());
+        assert_eq!(next_wake_time(), None);
+        assert_eq!(millis(), 10);
+
+        // A stale handle does not abort the next task in the same slot.
+        let next = executor.spawn(Delay::wait_for(Duration::from_millis(10))).unwrap();
+        handle.abort();
+        assert!(!next.is_finished());
+        executor.block_on(next);
+        assert_eq!(millis(), 20);
+    }
+
+    #[test]
+    fn wakers_outliving_their_executor_do_nothing() {
+        let _clock = virtual_clock::lock_for_test();
+        let wakers = RefCell::new(std::vec::Vec::new());
+        let store_waker = |cx: &mut Context<'_>| wakers.borrow_mut().push(cx.waker().clone());
+        {
+            let executor = pin!(Executor::<1>::new());
+            let executor = executor.into_ref();
+            executor
+                .spawn(poll_fn(|cx| {
+                    store_waker(cx);
+                    Poll::Pending
+                }))
+                .unwrap();
+            executor.block_on(poll_fn(|cx| {
+                store_waker(cx);
+                if wakers.borrow().len() < 2 {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Poll::Ready(())
+            }));
+        }
+        // Neither the header of the top-level future nor the one of the task
+        // exists anymore.
+        assert_eq!(wakers.borrow().len(), 3);
+        for waker in wakers.take() {
+            waker.wake();
+        }
+    }
 }
@@ -1,5 +1,7 @@
 use arduino_hal::{pac::TC2, simple_pwm::Prescaler, port::{Pin, mode::Output}, hal::port::PD3};
 
+use crate::{config::CPU_FREQUENCY, stepper::StepOutput};
+
 pub struct Timer2Freq {
     timer: TC2,
 }
@@ -25,13 +27,20 @@ impl Timer2Freq {
     }
 }
 
-pub struct FreqPinPD3<'a> {
+/// Clock dividers of Timer2, the smallest first
+const PRESCALERS: [u32; 7] = [1, 8, 32, 64, 128, 256, 1024];
+
+/// Timer2 cannot generate the frequency at the CPU clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreqOutOfRange;
+
+pub struct FreqPinPD3 {
     _pin: Pin<Output, PD3>,
-    timer: &'a Timer2Freq,
+    timer: Timer2Freq,
 }
 
-impl FreqPinPD3<'_> {
-    pub fn new(timer: &Timer2Freq, pin: Pin<Output, PD3>) -> FreqPinPD3 {
+impl FreqPinPD3 {
+    pub fn new(timer: Timer2Freq, pin: Pin<Output, PD3>) -> FreqPinPD3 {
         FreqPinPD3 { timer, _pin: pin }
     }
 
@@ -43,22 +52,48 @@ impl FreqPinPD3<'_> {
         self.timer.timer.tccr2a.modify(|_r, w| w.com2b().disconnected());
     }
 
-    pub fn set_freq(&mut self, freq: u16) {
-        let (prescaler, numerator) = match freq {
-            40..=199 => (Prescaler::Prescale1024, 15625),
-            200..=999 => (Prescaler::Prescale256, 62500),
-            1000..=4799 => (Prescaler::Prescale64, 250_000),
-            4800..=35000 => (Prescaler::Prescale8, 2_000_000),
-            _ => panic!(),
-        };
+    /// Sets the output frequency in Hz, using the smallest prescaler that
+    /// fits. Frequencies of 0 and below the lowest one Timer2 can generate,
+    /// 31 Hz at 16 MHz, are rejected.
+    pub fn set_freq(&mut self, freq: u16) -> Result<(), FreqOutOfRange> {
+        if freq == 0 {
+            return Err(FreqOutOfRange);
+        }
+        // The pin toggles on every compare match, so a period takes two
+        // cycles of `ocr2a + 1` timer counts.
+        let (prescaler, counts) = PRESCALERS
+            .into_iter()
+            .map(|prescaler| (prescaler, CPU_FREQUENCY / prescaler / (2 * freq as u32)))
+            .find(|&(_, counts)| counts <= 256)
+            .ok_or(FreqOutOfRange)?;
         self.timer.timer.tccr2b.modify(|_r, w| match prescaler {
-            Prescaler::Direct => w.cs2().direct(),
-            Prescaler::Prescale8 => w.cs2().prescale_8(),
-            Prescaler::Prescale64 => w.cs2().prescale_64(),
-            Prescaler::Prescale256 => w.cs2().prescale_256(),
-            Prescaler::Prescale1024 => w.cs2().prescale_1024(),
+            1 => w.cs2().direct(),
+            8 => w.cs2().prescale_8(),
+            32 => w.cs2().prescale_32(),
+            64 => w.cs2().prescale_64(),
+            128 => w.cs2().prescale_128(),
+            256 => w.cs2().prescale_256(),
+            _ => w.cs2().prescale_1024(),
         });
-        let reg = (numerator / (2 * freq) as u32) as u8;
+        // At least 1, the next smaller prescaler would have fit otherwise.
+        let reg = (counts - 1) as u8;
         self.timer.timer.ocr2a.write(|w| w.bits(reg));
+        Ok(())
+    }
+}
+
+impl StepOutput for FreqPinPD3 {
+    type Error = FreqOutOfRange;
+
+    fn set_freq(&mut self, freq: u16) -> Result<(), FreqOutOfRange> {
+        FreqPinPD3::set_freq(self, freq)
+    }
+
+    fn enable(&mut self) {
+        FreqPinPD3::enable(self)
+    }
+
+    fn disable(&mut self) {
+        FreqPinPD3::disable(self)
     }
 }
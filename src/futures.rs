@@ -1,3 +1,13 @@
+pub mod atomic_waker;
+pub mod channel;
 pub mod delay;
+pub mod event_group;
 pub mod join;
+pub mod mutex;
+pub mod select;
+pub mod semaphore;
+pub mod signal;
+pub mod ticker;
+pub mod timeout;
+pub mod waker_set;
 pub mod yield_now;
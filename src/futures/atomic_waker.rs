@@ -0,0 +1,152 @@
+//! Lets an interrupt handler wake exactly the task that waits for it.
+//!
+//! ```
+//! static INT0_WAKER: AtomicWaker = AtomicWaker::new();
+//! static INT0_FIRED: AtomicBool = AtomicBool::new(false);
+//!
+//! #[avr_device::interrupt(atmega328p)]
+//! fn INT0() {
+//!     INT0_FIRED.store(true, Ordering::Release);
+//!     INT0_WAKER.wake();
+//! }
+//!
+//! async fn wait_for_edge() {
+//!     INT0_WAKER.wait_until(|| INT0_FIRED.load(Ordering::Acquire)).await;
+//!     INT0_FIRED.store(false, Ordering::Release);
+//! }
+//! ```
+
+use core::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use crate::backend::{free, Mutex};
+
+/// Holds the waker of at most one task. All methods use critical sections, so
+/// they can be called from interrupt handlers and inside `free`.
+pub struct AtomicWaker {
+    waker: Mutex<Cell<Option<Waker>>>,
+}
+
+impl AtomicWaker {
+    pub const fn new() -> Self {
+        Self {
+            waker: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Registers `waker`, replacing the one registered before.
+    pub fn register(&self, waker: &Waker) {
+        free(|cs| {
+            let cell = self.waker.borrow(cs);
+            match cell.take() {
+                Some(old) if old.will_wake(waker) => cell.set(Some(old)),
+                _ => cell.set(Some(waker.clone())),
+            }
+        });
+    }
+
+    /// Removes the registered waker.
+    pub fn take(&self) -> Option<Waker> {
+        free(|cs| self.waker.borrow(cs).take())
+    }
+
+    /// Wakes the registered task, if any. Every registration wakes at most
+    /// once.
+    pub fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    /// Waits until `condition` returns true, checking it whenever this waker
+    /// is woken.
+    pub fn wait_until<F: FnMut() -> bool>(&self, condition: F) -> WaitUntil<'_, F> {
+        WaitUntil {
+            waker: self,
+            condition,
+        }
+    }
+}
+
+pub struct WaitUntil<'a, F> {
+    waker: &'a AtomicWaker,
+    condition: F,
+}
+
+impl<F: FnMut() -> bool + Unpin> Future for WaitUntil<'_, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        // Register first, an interrupt after the check then wakes us again.
+        this.waker.register(cx.waker());
+        if (this.condition)() {
+            this.waker.take();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{future::poll_fn, pin::pin};
+
+    use super::*;
+    use crate::{
+        executor::Executor,
+        futures::yield_now::yield_now,
+        simulation::block_on,
+        timers::virtual_clock,
+    };
+
+    #[test]
+    fn wakes_only_the_registered_task() {
+        let _clock = virtual_clock::lock_for_test();
+        let waker = AtomicWaker::new();
+        let fired = Cell::new(false);
+        let other_polls = Cell::new(0);
+        let executor = pin!(Executor::<2>::new());
+        let executor = executor.into_ref();
+        let waiting = executor.spawn(waker.wait_until(|| fired.get())).unwrap();
+        executor
+            .spawn(poll_fn(|_| {
+                other_polls.set(other_polls.get() + 1);
+                Poll::Pending
+            }))
+            .unwrap();
+
+        executor.block_on(async {
+            // Both tasks are polled once and wait.
+            yield_now().await;
+            assert!(!waiting.is_finished());
+            assert_eq!(other_polls.get(), 1);
+
+            fired.set(true);
+            waker.wake();
+            yield_now().await;
+            assert!(waiting.is_finished());
+            assert_eq!(other_polls.get(), 1);
+        });
+        // Waking used up the registration.
+        assert!(waker.take().is_none());
+    }
+
+    #[test]
+    fn wait_until_returns_right_away_if_the_condition_holds() {
+        let _clock = virtual_clock::lock_for_test();
+        let waker = AtomicWaker::new();
+        let mut checks = 0;
+        block_on(waker.wait_until(|| {
+            checks += 1;
+            true
+        }));
+        assert_eq!(checks, 1);
+        assert!(waker.take().is_none());
+    }
+}
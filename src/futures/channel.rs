@@ -0,0 +1,282 @@
+//! Bounded async channel for messages between tasks and from interrupts.
+//!
+//! Any number of tasks can send, one task receives. Without compare-and-swap
+//! on the AVR, the queue is guarded by a critical section either way, so a
+//! single-producer channel is the same type with only one [`Sender`].
+//!
+//! ```
+//! static COMMANDS: Channel<MotorCommand, 4> = Channel::new();
+//!
+//! // button task
+//! COMMANDS.send(MotorCommand::Stop).await;
+//!
+//! // motor task
+//! let command = COMMANDS.recv().await;
+//! ```
+
+use core::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use heapless::Deque;
+
+use crate::{
+    backend::{free, Mutex},
+    futures::waker_set::{WaitNode, WakerSet},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full, the message is handed back
+    Full(T),
+}
+
+struct State<T, const N: usize> {
+    queue: Deque<T, N>,
+    receiver: Option<Waker>,
+    senders: WakerSet,
+}
+
+impl<T, const N: usize> State<T, N> {
+    fn try_send(&mut self, message: T) -> Result<(), T> {
+        self.queue.push_back(message)?;
+        if let Some(waker) = self.receiver.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        let message = self.queue.pop_front()?;
+        self.senders.wake_all();
+        Some(message)
+    }
+}
+
+pub struct Channel<T, const N: usize> {
+    state: Mutex<RefCell<State<T, N>>>,
+}
+
+impl<T, const N: usize> Channel<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(State {
+                queue: Deque::new(),
+                receiver: None,
+                senders: WakerSet::new(),
+            })),
+        }
+    }
+
+    /// Queues `message` if there is room. Can be called from interrupt
+    /// handlers.
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        free(|cs| self.state.borrow(cs).borrow_mut().try_send(message)).map_err(TrySendError::Full)
+    }
+
+    /// Waits for room in the queue and sends `message`.
+    pub fn send(&self, message: T) -> SendFuture<'_, T, N> {
+        SendFuture {
+            channel: self,
+            message: Some(message),
+            node: WaitNode::new(),
+        }
+    }
+
+    /// Takes the oldest message, if any.
+    #[allow(dead_code)]
+    pub fn try_recv(&self) -> Option<T> {
+        free(|cs| self.state.borrow(cs).borrow_mut().try_recv())
+    }
+
+    /// Waits for the next message. Only one task may wait at a time.
+    pub fn recv(&self) -> RecvFuture<'_, T, N> {
+        RecvFuture { channel: self }
+    }
+
+    pub fn sender(&self) -> Sender<'_, T, N> {
+        Sender { channel: self }
+    }
+
+    pub fn receiver(&self) -> Receiver<'_, T, N> {
+        Receiver { channel: self }
+    }
+
+    /// Both ends of the channel, for a single producer and consumer
+    #[allow(dead_code)]
+    pub fn split(&self) -> (Sender<'_, T, N>, Receiver<'_, T, N>) {
+        (self.sender(), self.receiver())
+    }
+}
+
+/// Sending end of a [`Channel`], can be copied for more producers
+pub struct Sender<'a, T, const N: usize> {
+    channel: &'a Channel<T, N>,
+}
+
+impl<T, const N: usize> Clone for Sender<'_, T, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, const N: usize> Copy for Sender<'_, T, N> {}
+
+impl<'a, T, const N: usize> Sender<'a, T, N> {
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        self.channel.try_send(message)
+    }
+
+    pub fn send(&self, message: T) -> SendFuture<'a, T, N> {
+        self.channel.send(message)
+    }
+}
+
+/// Receiving end of a [`Channel`]
+pub struct Receiver<'a, T, const N: usize> {
+    channel: &'a Channel<T, N>,
+}
+
+impl<'a, T, const N: usize> Receiver<'a, T, N> {
+    #[allow(dead_code)]
+    pub fn try_recv(&self) -> Option<T> {
+        self.channel.try_recv()
+    }
+
+    pub fn recv(&self) -> RecvFuture<'a, T, N> {
+        self.channel.recv()
+    }
+}
+
+/// Future for [`Channel::send`]. Dropping it before it completes drops the
+/// message.
+pub struct SendFuture<'a, T, const N: usize> {
+    channel: &'a Channel<T, N>,
+    message: Option<T>,
+    node: WaitNode,
+}
+
+impl<T, const N: usize> Future for SendFuture<'_, T, N> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The message is moved into the queue, it is never pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let node = unsafe { Pin::new_unchecked(&this.node) };
+        let Some(message) = this.message.take() else {
+            return Poll::Ready(());
+        };
+        free(|cs| {
+            let mut state = this.channel.state.borrow(cs).borrow_mut();
+            match state.try_send(message) {
+                Ok(()) => {
+                    state.senders.remove(&node);
+                    Poll::Ready(())
+                }
+                Err(message) => {
+                    this.message = Some(message);
+                    state.senders.register(node, cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+impl<T, const N: usize> Drop for SendFuture<'_, T, N> {
+    fn drop(&mut self) {
+        free(|cs| self.channel.state.borrow(cs).borrow_mut().senders.remove(&self.node));
+    }
+}
+
+/// Future for [`Channel::recv`]
+pub struct RecvFuture<'a, T, const N: usize> {
+    channel: &'a Channel<T, N>,
+}
+
+impl<T, const N: usize> Future for RecvFuture<'_, T, N> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        free(|cs| {
+            let mut state = self.channel.state.borrow(cs).borrow_mut();
+            match state.try_recv() {
+                Some(message) => Poll::Ready(message),
+                None => {
+                    state.receiver = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{array, cell::RefCell};
+
+    use super::*;
+    use crate::{
+        futures::{delay::Delay, join::join},
+        simulation::{block_on, block_on_with_tasks},
+        time::Duration,
+        timers::{millis, virtual_clock},
+    };
+
+    #[test]
+    fn full_channel_holds_the_sender_back() {
+        let _clock = virtual_clock::lock_for_test();
+        let channel: Channel<u8, 2> = Channel::new();
+        let sent = RefCell::new(std::vec::Vec::new());
+        let received = RefCell::new(std::vec::Vec::new());
+        let send = async {
+            for message in 0..5 {
+                channel.send(message).await;
+                sent.borrow_mut().push(millis());
+            }
+        };
+        let recv = async {
+            for _ in 0..5 {
+                Delay::wait_for(Duration::from_millis(100)).await;
+                let message = channel.recv().await;
+                received.borrow_mut().push((millis(), message));
+            }
+        };
+        block_on(join(send, recv));
+        assert_eq!(*sent.borrow(), [0, 0, 100, 200, 300]);
+        assert_eq!(*received.borrow(), [(100, 0), (200, 1), (300, 2), (400, 3), (500, 4)]);
+    }
+
+    #[test]
+    fn try_send_hands_the_message_back_when_full() {
+        let channel: Channel<u8, 1> = Channel::new();
+        assert_eq!(channel.try_send(1), Ok(()));
+        assert_eq!(channel.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(channel.try_recv(), Some(1));
+        assert_eq!(channel.try_recv(), None);
+    }
+
+    #[test]
+    fn waiting_senders_all_get_through() {
+        const SENDERS: usize = 6;
+        let _clock = virtual_clock::lock_for_test();
+        let channel: Channel<usize, 1> = Channel::new();
+        channel.try_send(0).unwrap();
+        let senders = array::from_fn::<_, SENDERS, _>(|i| {
+            let channel = &channel;
+            async move { channel.send(i + 1).await }
+        });
+        let mut received = block_on_with_tasks(senders, async {
+            let mut received = std::vec::Vec::new();
+            for _ in 0..=SENDERS {
+                received.push(channel.recv().await);
+            }
+            received
+        });
+        received.sort();
+        assert_eq!(received, (0..=SENDERS).collect::<std::vec::Vec<_>>());
+    }
+}
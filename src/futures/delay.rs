@@ -1,30 +1,37 @@
-use avr_device::interrupt::Mutex;
 use core::{
-    cell::Cell,
     future::Future,
     pin::Pin,
     task::{Context, Poll},
 };
 
-use crate::timers::{millis, WAKERS};
-
-static NEXT_DELAY_ID: Mutex<Cell<u16>> = Mutex::new(Cell::new(0));
+use crate::{
+    time::{Duration, Instant},
+    timers::{micros, schedule_wakeup, TimerNode, TIMER_JITTER_US},
+};
 
 pub struct Delay {
-    wake_time: u32,
-    id: u16,
+    wake_time: Instant,
+    timer: TimerNode,
 }
 
 impl Delay {
-    pub fn wait_for(delay: u32) -> Self {
-        let wake_time = millis() + delay;
-        let id = avr_device::interrupt::free(|cs| {
-            let next_id = NEXT_DELAY_ID.borrow(cs);
-            let id = next_id.get();
-            next_id.set(id.wrapping_add(1));
-            id
-        });
-        Self { wake_time, id }
+    pub fn wait_for(delay: Duration) -> Self {
+        Self::until(Instant::now() + delay)
+    }
+
+    pub fn until(wake_time: Instant) -> Self {
+        Self {
+            wake_time,
+            timer: TimerNode::new(),
+        }
+    }
+
+    /// Waits for `delay` microseconds, e.g. for step pulses or LCD commands.
+    pub fn wait_for_us(delay: u32) -> DelayUs {
+        DelayUs {
+            wake_time: micros().wrapping_add(delay),
+            timer: TimerNode::new(),
+        }
     }
 }
 
@@ -32,21 +39,103 @@ impl Future for Delay {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let now = millis();
-        if now < self.wake_time {
-            avr_device::interrupt::free(|cs| {
-                let mut wakers = WAKERS.borrow(cs).borrow_mut();
-                if wakers.has_capacity() {
-                    match wakers.replace_or_push(self.wake_time, self.id, cx.waker().clone()) {
-                        Ok(_) => {}
-                        Err(_) => {}
-                    }
-                } else {
-                }
-            });
+        let this = self.into_ref();
+        if Instant::now() < this.wake_time {
+            let timer = unsafe { this.map_unchecked(|delay| &delay.timer) };
+            schedule_wakeup(timer, this.wake_time, cx.waker().clone());
             Poll::Pending
         } else {
             Poll::Ready(())
         }
     }
 }
+
+/// Microsecond delay, see [`Delay::wait_for_us`].
+///
+/// Sleeps on the millisecond timers until shortly before the deadline and
+/// keeps the task ready for the rest, so it is neither late nor early.
+pub struct DelayUs {
+    wake_time: u32,
+    timer: TimerNode,
+}
+
+impl Future for DelayUs {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.into_ref();
+        // `micros` wraps around after 71 minutes, compare the difference.
+        let remaining = this.wake_time.wrapping_sub(micros()) as i32;
+        if remaining <= 0 {
+            return Poll::Ready(());
+        }
+        let coarse = (remaining as u32).saturating_sub(TIMER_JITTER_US);
+        if coarse > 0 {
+            let wake_time = Instant::now() + Duration::from_millis((coarse + 999) / 1000);
+            let timer = unsafe { this.map_unchecked(|delay| &delay.timer) };
+            schedule_wakeup(timer, wake_time, cx.waker().clone());
+        } else {
+            // Close to the deadline, poll again right away.
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, pin::pin};
+
+    use super::*;
+    use crate::{
+        backend::free,
+        futures::{join::join_array, select::select},
+        simulation::block_on,
+        timers::{millis, virtual_clock, WAKERS},
+    };
+
+    #[test]
+    fn any_number_of_delays_wake_on_time() {
+        let _clock = virtual_clock::lock_for_test();
+        let woken = RefCell::new(std::vec::Vec::new());
+        let delays: [_; 16] = core::array::from_fn(|i| {
+            let woken = &woken;
+            async move {
+                Delay::wait_for(Duration::from_millis(100 * (16 - i as u32))).await;
+                woken.borrow_mut().push(millis());
+            }
+        });
+        block_on(join_array(delays));
+        let expected: std::vec::Vec<u32> = (1..=16).map(|i| 100 * i).collect();
+        assert_eq!(*woken.borrow(), expected);
+    }
+
+    #[test]
+    fn dropped_delay_leaves_the_queue() {
+        let _clock = virtual_clock::lock_for_test();
+        block_on(select(
+            Delay::wait_for(Duration::from_millis(10)),
+            Delay::wait_for(Duration::from_millis(1000)),
+        ));
+        assert_eq!(millis(), 10);
+        assert_eq!(free(|cs| WAKERS.borrow(cs).borrow().next_wake_time()), None);
+    }
+
+    #[test]
+    fn each_delay_has_its_own_timer() {
+        let _clock = virtual_clock::lock_for_test();
+        let next_wake_time = || free(|cs| WAKERS.borrow(cs).borrow().next_wake_time());
+        block_on(async {
+            let mut delay = pin!(Delay::wait_for(Duration::from_millis(100)));
+            // The second poll updates the timer of the first one.
+            select(delay.as_mut(), async {}).await;
+            select(delay.as_mut(), async {}).await;
+            // A delay that never registered a timer does not remove another one.
+            drop(Delay::wait_for(Duration::from_millis(100)));
+            assert_eq!(next_wake_time(), Some(Instant::from_millis(100)));
+            delay.await;
+            assert_eq!(next_wake_time(), None);
+        });
+        assert_eq!(millis(), 100);
+    }
+}
@@ -0,0 +1,179 @@
+//! Up to eight flags that tasks can wait for in combination.
+//!
+//! Flags stay set until they are cleared, so a task that starts waiting late
+//! still sees them.
+//!
+//! ```
+//! const HOMED: u8 = 1 << 0;
+//! const DISPLAY_READY: u8 = 1 << 1;
+//! static STARTUP: EventGroup = EventGroup::new();
+//!
+//! // motor task
+//! STARTUP.set(HOMED);
+//!
+//! // button task
+//! STARTUP.wait_all(HOMED | DISPLAY_READY).await;
+//! ```
+
+use core::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    backend::{free, Mutex},
+    futures::waker_set::{WaitNode, WakerSet},
+};
+
+struct State {
+    flags: u8,
+    waiters: WakerSet,
+}
+
+pub struct EventGroup {
+    state: Mutex<RefCell<State>>,
+}
+
+impl EventGroup {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(State {
+                flags: 0,
+                waiters: WakerSet::new(),
+            })),
+        }
+    }
+
+    /// Sets `flags` and wakes the waiting tasks. Can be called from interrupt
+    /// handlers.
+    pub fn set(&self, flags: u8) {
+        free(|cs| {
+            let mut state = self.state.borrow(cs).borrow_mut();
+            state.flags |= flags;
+            state.waiters.wake_all();
+        });
+    }
+
+    #[allow(dead_code)]
+    pub fn clear(&self, flags: u8) {
+        free(|cs| self.state.borrow(cs).borrow_mut().flags &= !flags);
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self) -> u8 {
+        free(|cs| self.state.borrow(cs).borrow().flags)
+    }
+
+    /// Waits until at least one of `flags` is set. Returns the ones that are.
+    #[allow(dead_code)]
+    pub fn wait_any(&self, flags: u8) -> WaitFuture<'_> {
+        WaitFuture {
+            group: self,
+            flags,
+            all: false,
+            node: WaitNode::new(),
+        }
+    }
+
+    /// Waits until all of `flags` are set.
+    pub fn wait_all(&self, flags: u8) -> WaitFuture<'_> {
+        WaitFuture {
+            group: self,
+            flags,
+            all: true,
+            node: WaitNode::new(),
+        }
+    }
+}
+
+/// Future for [`EventGroup::wait_any`] and [`EventGroup::wait_all`]
+pub struct WaitFuture<'a> {
+    group: &'a EventGroup,
+    flags: u8,
+    all: bool,
+    node: WaitNode,
+}
+
+impl Future for WaitFuture<'_> {
+    type Output = u8;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.into_ref();
+        let node = unsafe { this.map_unchecked(|future| &future.node) };
+        free(|cs| {
+            let mut state = this.group.state.borrow(cs).borrow_mut();
+            let set = state.flags & this.flags;
+            let done = if this.all { set == this.flags } else { set != 0 };
+            if done {
+                state.waiters.remove(&node);
+                Poll::Ready(set)
+            } else {
+                state.waiters.register(node, cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl Drop for WaitFuture<'_> {
+    fn drop(&mut self) {
+        free(|cs| self.group.state.borrow(cs).borrow_mut().waiters.remove(&self.node));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::{
+        futures::{delay::Delay, join::join},
+        simulation::block_on,
+        time::Duration,
+        timers::{millis, virtual_clock},
+    };
+
+    const A: u8 = 1 << 0;
+    const B: u8 = 1 << 1;
+    const C: u8 = 1 << 2;
+
+    #[test]
+    fn wait_all_waits_for_every_flag() {
+        let _clock = virtual_clock::lock_for_test();
+        let group = EventGroup::new();
+        let done = Cell::new(None);
+        let wait = async {
+            assert_eq!(group.wait_all(A | B).await, A | B);
+            done.set(Some(millis()));
+        };
+        let set = async {
+            group.set(A);
+            Delay::wait_for(Duration::from_millis(10)).await;
+            group.set(C);
+            Delay::wait_for(Duration::from_millis(10)).await;
+            group.set(B);
+        };
+        block_on(join(wait, set));
+        assert_eq!(done.get(), Some(20));
+        assert_eq!(group.get(), A | B | C);
+    }
+
+    #[test]
+    fn wait_any_returns_the_set_flags() {
+        let _clock = virtual_clock::lock_for_test();
+        let group = EventGroup::new();
+        group.set(B | C);
+        // Flags that were set before are seen by a late waiter.
+        assert_eq!(block_on(group.wait_any(A | B)), B);
+        group.clear(B);
+        let wait = async { group.wait_any(A | B).await };
+        let set = async {
+            Delay::wait_for(Duration::from_millis(5)).await;
+            group.set(A);
+        };
+        assert_eq!(block_on(join(wait, set)), (A, ()));
+        assert_eq!(millis(), 5);
+    }
+}
@@ -0,0 +1,231 @@
+//! Async mutex for data that is used across `.await` points by several tasks.
+//!
+//! A `RefCell` borrow that is held while the task waits panics as soon as
+//! another task borrows the same cell. [`Mutex::lock`] instead parks the
+//! other task until the guard is dropped.
+//!
+//! ```
+//! let lcd = Mutex::new(lcd);
+//!
+//! let mut lcd = lcd.lock().await;
+//! lcd.set_position(0, 0).await;
+//! lcd.print("Mag Loop").await;
+//! ```
+//!
+//! The mutex is for tasks only, interrupt handlers can not wait for it. It is
+//! not fair: a task that unlocks and locks again without an `.await` in
+//! between keeps it.
+
+use core::{
+    cell::{RefCell, UnsafeCell},
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    backend::{self, free},
+    futures::waker_set::{WaitNode, WakerSet},
+};
+
+struct State {
+    locked: bool,
+    waiters: WakerSet,
+}
+
+pub struct Mutex<T> {
+    state: backend::Mutex<RefCell<State>>,
+    value: UnsafeCell<T>,
+}
+
+// The value is only reached through a guard, and there is at most one guard.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: backend::Mutex::new(RefCell::new(State {
+                locked: false,
+                waiters: WakerSet::new(),
+            })),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Locks the mutex if it is free.
+    #[allow(dead_code)]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        free(|cs| {
+            let mut state = self.state.borrow(cs).borrow_mut();
+            if state.locked {
+                return None;
+            }
+            state.locked = true;
+            Some(MutexGuard { mutex: self })
+        })
+    }
+
+    /// Waits until the mutex is free and locks it.
+    pub fn lock(&self) -> LockFuture<'_, T> {
+        LockFuture {
+            mutex: self,
+            node: WaitNode::new(),
+        }
+    }
+
+    fn unlock(&self) {
+        free(|cs| {
+            let mut state = self.state.borrow(cs).borrow_mut();
+            state.locked = false;
+            state.waiters.wake_all();
+        });
+    }
+}
+
+/// Future for [`Mutex::lock`]
+pub struct LockFuture<'a, T> {
+    mutex: &'a Mutex<T>,
+    node: WaitNode,
+}
+
+impl<'a, T> Future for LockFuture<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.into_ref();
+        let mutex = this.mutex;
+        let node = unsafe { this.map_unchecked(|future| &future.node) };
+        free(|cs| {
+            let mut state = mutex.state.borrow(cs).borrow_mut();
+            if state.locked {
+                state.waiters.register(node, cx.waker());
+                Poll::Pending
+            } else {
+                state.waiters.remove(&node);
+                state.locked = true;
+                Poll::Ready(MutexGuard { mutex })
+            }
+        })
+    }
+}
+
+impl<T> Drop for LockFuture<'_, T> {
+    fn drop(&mut self) {
+        free(|cs| self.mutex.state.borrow(cs).borrow_mut().waiters.remove(&self.node));
+    }
+}
+
+/// Access to the value of a locked [`Mutex`], unlocks it when dropped.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{array, pin::pin};
+
+    use super::*;
+    use crate::{
+        executor::{Executor, TaskId},
+        futures::{delay::Delay, join::join, select::select, yield_now::yield_now},
+        simulation::{block_on, block_on_with_tasks},
+        time::Duration,
+        timers::{millis, virtual_clock},
+    };
+
+    #[test]
+    fn lock_waits_for_the_guard_across_await_points() {
+        let _clock = virtual_clock::lock_for_test();
+        let mutex = Mutex::new(std::vec::Vec::new());
+        let holder = async {
+            let mut values = mutex.lock().await;
+            values.push(1);
+            Delay::wait_for(Duration::from_millis(100)).await;
+            values.push(2);
+        };
+        let waiter = async {
+            yield_now().await;
+            assert!(mutex.try_lock().is_none());
+            let mut values = mutex.lock().await;
+            assert_eq!(millis(), 100);
+            values.push(3);
+        };
+        block_on(join(holder, waiter));
+        assert_eq!(*mutex.try_lock().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn waiting_tasks_get_the_lock_one_after_the_other() {
+        const TASKS: usize = 6;
+        let _clock = virtual_clock::lock_for_test();
+        let mutex = Mutex::new(std::vec::Vec::new());
+        let tasks = array::from_fn::<_, TASKS, _>(|task| {
+            let mutex = &mutex;
+            async move {
+                let mut times = mutex.lock().await;
+                times.push((task, millis()));
+                Delay::wait_for(Duration::from_millis(10)).await;
+            }
+        });
+        block_on_with_tasks(tasks, async {});
+        let times: std::vec::Vec<_> = (0..TASKS).map(|i| (i, 10 * i as u32)).collect();
+        assert_eq!(*mutex.try_lock().unwrap(), times);
+    }
+
+    #[test]
+    fn waiting_tasks_sleep_until_the_mutex_is_unlocked() {
+        const TASKS: usize = 6;
+        let _clock = virtual_clock::lock_for_test();
+        let mutex = Mutex::new(());
+        let executor = pin!(Executor::<TASKS>::new());
+        let executor = executor.into_ref();
+        for _ in 0..TASKS {
+            let mutex = &mutex;
+            executor
+                .spawn(async move {
+                    let _guard = mutex.lock().await;
+                })
+                .unwrap();
+        }
+        executor.block_on(async {
+            let _guard = mutex.lock().await;
+            Delay::wait_for(Duration::from_millis(100)).await;
+            for task in 0..TASKS {
+                assert_eq!(executor.stats(TaskId(task as u8)).unwrap().polls, 1);
+            }
+        });
+        executor.run();
+        assert!(mutex.try_lock().is_some());
+    }
+
+    #[test]
+    fn dropped_lock_futures_stop_waiting() {
+        let _clock = virtual_clock::lock_for_test();
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock();
+        block_on(select(mutex.lock(), async {}));
+        assert!(free(|cs| mutex.state.borrow(cs).borrow().waiters.is_empty()));
+        drop(guard);
+    }
+}
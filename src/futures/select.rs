@@ -0,0 +1,321 @@
+//! From embassy: https://github.com/embassy-rs/embassy/blob/main/embassy-futures/src/select.rs
+//! Wait for the first of several futures to complete.
+//!
+//! The futures that did not finish are dropped together with the select
+//! future, which also removes their timers from the queue.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Result for [`select`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Either<A, B> {
+    /// First future finished first.
+    First(A),
+    /// Second future finished first.
+    Second(B),
+}
+
+/// Wait for one of two futures to complete.
+///
+/// This function returns a new future which polls all the futures.
+/// When one of them completes, it will complete with its result value.
+///
+/// The other future is dropped.
+///
+/// # Examples
+///
+/// ```
+/// match select(wait_for_button(), Delay::wait_for(Duration::from_secs(30))).await {
+///     Either::First(()) => start_motor(),
+///     Either::Second(()) => show_idle_screen(),
+/// }
+/// ```
+#[allow(dead_code)]
+pub fn select<A, B>(a: A, b: B) -> Select<A, B>
+where
+    A: Future,
+    B: Future,
+{
+    Select { a, b }
+}
+
+/// Future for the [`select`] function.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Select<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: Unpin, B: Unpin> Unpin for Select<A, B> {}
+
+impl<A, B> Future for Select<A, B>
+where
+    A: Future,
+    B: Future,
+{
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let a = unsafe { Pin::new_unchecked(&mut this.a) };
+        let b = unsafe { Pin::new_unchecked(&mut this.b) };
+        if let Poll::Ready(x) = a.poll(cx) {
+            return Poll::Ready(Either::First(x));
+        }
+        if let Poll::Ready(x) = b.poll(cx) {
+            return Poll::Ready(Either::Second(x));
+        }
+        Poll::Pending
+    }
+}
+
+// ====================================================================
+
+/// Result for [`select3`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Either3<A, B, C> {
+    /// First future finished first.
+    First(A),
+    /// Second future finished first.
+    Second(B),
+    /// Third future finished first.
+    Third(C),
+}
+
+/// Same as [`select`], but with more futures.
+#[allow(dead_code)]
+pub fn select3<A, B, C>(a: A, b: B, c: C) -> Select3<A, B, C>
+where
+    A: Future,
+    B: Future,
+    C: Future,
+{
+    Select3 { a, b, c }
+}
+
+/// Future for the [`select3`] function.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Select3<A, B, C> {
+    a: A,
+    b: B,
+    c: C,
+}
+
+impl<A, B, C> Future for Select3<A, B, C>
+where
+    A: Future,
+    B: Future,
+    C: Future,
+{
+    type Output = Either3<A::Output, B::Output, C::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let a = unsafe { Pin::new_unchecked(&mut this.a) };
+        let b = unsafe { Pin::new_unchecked(&mut this.b) };
+        let c = unsafe { Pin::new_unchecked(&mut this.c) };
+        if let Poll::Ready(x) = a.poll(cx) {
+            return Poll::Ready(Either3::First(x));
+        }
+        if let Poll::Ready(x) = b.poll(cx) {
+            return Poll::Ready(Either3::Second(x));
+        }
+        if let Poll::Ready(x) = c.poll(cx) {
+            return Poll::Ready(Either3::Third(x));
+        }
+        Poll::Pending
+    }
+}
+
+// ====================================================================
+
+/// Result for [`select4`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Either4<A, B, C, D> {
+    /// First future finished first.
+    First(A),
+    /// Second future finished first.
+    Second(B),
+    /// Third future finished first.
+    Third(C),
+    /// Fourth future finished first.
+    Fourth(D),
+}
+
+/// Same as [`select`], but with more futures.
+#[allow(dead_code)]
+pub fn select4<A, B, C, D>(a: A, b: B, c: C, d: D) -> Select4<A, B, C, D>
+where
+    A: Future,
+    B: Future,
+    C: Future,
+    D: Future,
+{
+    Select4 { a, b, c, d }
+}
+
+/// Future for the [`select4`] function.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Select4<A, B, C, D> {
+    a: A,
+    b: B,
+    c: C,
+    d: D,
+}
+
+impl<A, B, C, D> Future for Select4<A, B, C, D>
+where
+    A: Future,
+    B: Future,
+    C: Future,
+    D: Future,
+{
+    type Output = Either4<A::Output, B::Output, C::Output, D::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let a = unsafe { Pin::new_unchecked(&mut this.a) };
+        let b = unsafe { Pin::new_unchecked(&mut this.b) };
+        let c = unsafe { Pin::new_unchecked(&mut this.c) };
+        let d = unsafe { Pin::new_unchecked(&mut this.d) };
+        if let Poll::Ready(x) = a.poll(cx) {
+            return Poll::Ready(Either4::First(x));
+        }
+        if let Poll::Ready(x) = b.poll(cx) {
+            return Poll::Ready(Either4::Second(x));
+        }
+        if let Poll::Ready(x) = c.poll(cx) {
+            return Poll::Ready(Either4::Third(x));
+        }
+        if let Poll::Ready(x) = d.poll(cx) {
+            return Poll::Ready(Either4::Fourth(x));
+        }
+        Poll::Pending
+    }
+}
+
+// ====================================================================
+
+/// Future for the [`select_array`] function.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SelectArray<Fut, const N: usize> {
+    inner: [Fut; N],
+}
+
+/// Creates a new future which will select over an array of futures.
+///
+/// The returned future will wait for any future to be ready. Upon
+/// completion the item resolved will be returned, along with the index of the
+/// future that was ready.
+///
+/// If the array is empty, the resulting future will be Pending forever.
+#[allow(dead_code)]
+pub fn select_array<Fut: Future, const N: usize>(arr: [Fut; N]) -> SelectArray<Fut, N> {
+    SelectArray { inner: arr }
+}
+
+impl<Fut: Future, const N: usize> Future for SelectArray<Fut, N> {
+    type Output = (Fut::Output, usize);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: Since `self` is pinned, `inner` cannot move. Since `inner` cannot move,
+        // its elements also cannot move. Therefore it is safe to access `inner` and pin
+        // references to the contained futures.
+        let item = unsafe {
+            self.get_unchecked_mut()
+                .inner
+                .iter_mut()
+                .enumerate()
+                .find_map(|(i, f)| match Pin::new_unchecked(f).poll(cx) {
+                    Poll::Pending => None,
+                    Poll::Ready(e) => Some((e, i)),
+                })
+        };
+
+        match item {
+            Some(res) => Poll::Ready(res),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::Cell, future::pending};
+
+    use super::*;
+    use crate::{
+        backend::free,
+        futures::delay::Delay,
+        simulation::block_on,
+        time::Duration,
+        timers::{millis, virtual_clock, WAKERS},
+    };
+
+    /// Sets `dropped` when the future is dropped.
+    struct DropFlag<'a, F> {
+        future: F,
+        dropped: &'a Cell<bool>,
+    }
+
+    impl<F: Future> Future for DropFlag<'_, F> {
+        type Output = F::Output;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            unsafe { self.map_unchecked_mut(|f| &mut f.future) }.poll(cx)
+        }
+    }
+
+    impl<F> Drop for DropFlag<'_, F> {
+        fn drop(&mut self) {
+            self.dropped.set(true);
+        }
+    }
+
+    fn wait_ms(ms: u32) -> Delay {
+        Delay::wait_for(Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn first_ready_future_wins() {
+        let _clock = virtual_clock::lock_for_test();
+        assert_eq!(block_on(select(async { 1 }, async { 2 })), Either::First(1));
+        assert_eq!(block_on(select(pending::<()>(), async { 2 })), Either::Second(2));
+        let third = select3(wait_ms(30), wait_ms(50), async {
+            wait_ms(10).await;
+            3
+        });
+        assert_eq!(block_on(third), Either3::Third(3));
+        assert_eq!(millis(), 10);
+        let fourth = select4(wait_ms(30), wait_ms(50), wait_ms(40), wait_ms(20));
+        assert_eq!(block_on(fourth), Either4::Fourth(()));
+        assert_eq!(millis(), 30);
+        assert_eq!(block_on(select_array([wait_ms(30), wait_ms(20), wait_ms(40)])), ((), 1));
+        assert_eq!(millis(), 50);
+    }
+
+    #[test]
+    fn losers_are_dropped_with_their_timers() {
+        let _clock = virtual_clock::lock_for_test();
+        let dropped = Cell::new(false);
+        let loser = DropFlag {
+            future: async {
+                wait_ms(1000).await;
+                wait_ms(1000).await;
+            },
+            dropped: &dropped,
+        };
+        block_on(async {
+            assert_eq!(select(wait_ms(10), loser).await, Either::First(()));
+            assert!(dropped.get());
+            assert_eq!(free(|cs| WAKERS.borrow(cs).borrow().next_wake_time()), None);
+        });
+        assert_eq!(millis(), 10);
+    }
+}
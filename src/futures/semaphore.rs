@@ -0,0 +1,174 @@
+//! Async counting semaphore, limits how many tasks use a resource at once.
+//!
+//! ```
+//! let i2c_access = Semaphore::new(1);
+//!
+//! let _permit = i2c_access.acquire().await;
+//! rtc.write(&time)?;
+//! // the permit is released when it is dropped
+//! ```
+
+use core::{
+    cell::RefCell,
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    backend::{free, Mutex},
+    futures::waker_set::{WaitNode, WakerSet},
+};
+
+struct State {
+    permits: usize,
+    waiters: WakerSet,
+}
+
+pub struct Semaphore {
+    state: Mutex<RefCell<State>>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(State {
+                permits,
+                waiters: WakerSet::new(),
+            })),
+        }
+    }
+
+    /// Takes a permit if one is available.
+    #[allow(dead_code)]
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        free(|cs| {
+            let mut state = self.state.borrow(cs).borrow_mut();
+            if state.permits == 0 {
+                return None;
+            }
+            state.permits -= 1;
+            Some(SemaphorePermit { semaphore: self })
+        })
+    }
+
+    /// Waits until a permit is available and takes it.
+    pub fn acquire(&self) -> AcquireFuture<'_> {
+        AcquireFuture {
+            semaphore: self,
+            node: WaitNode::new(),
+        }
+    }
+
+    /// Adds a permit and wakes the waiting tasks. Can be called from
+    /// interrupt handlers, e.g. to count events that tasks consume.
+    pub fn release(&self) {
+        free(|cs| {
+            let mut state = self.state.borrow(cs).borrow_mut();
+            state.permits += 1;
+            state.waiters.wake_all();
+        });
+    }
+
+    /// Number of permits that are not taken
+    #[allow(dead_code)]
+    pub fn available(&self) -> usize {
+        free(|cs| self.state.borrow(cs).borrow().permits)
+    }
+}
+
+/// Future for [`Semaphore::acquire`]
+pub struct AcquireFuture<'a> {
+    semaphore: &'a Semaphore,
+    node: WaitNode,
+}
+
+impl<'a> Future for AcquireFuture<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.into_ref();
+        let semaphore = this.semaphore;
+        let node = unsafe { this.map_unchecked(|future| &future.node) };
+        free(|cs| {
+            let mut state = semaphore.state.borrow(cs).borrow_mut();
+            if state.permits == 0 {
+                state.waiters.register(node, cx.waker());
+                Poll::Pending
+            } else {
+                state.waiters.remove(&node);
+                state.permits -= 1;
+                Poll::Ready(SemaphorePermit { semaphore })
+            }
+        })
+    }
+}
+
+impl Drop for AcquireFuture<'_> {
+    fn drop(&mut self) {
+        free(|cs| self.semaphore.state.borrow(cs).borrow_mut().waiters.remove(&self.node));
+    }
+}
+
+/// A taken permit, released when dropped
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keeps the permit taken, it can be given back with
+    /// [`Semaphore::release`].
+    #[allow(dead_code)]
+    pub fn forget(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{array, cell::RefCell};
+
+    use super::*;
+    use crate::{
+        futures::delay::Delay,
+        simulation::block_on_with_tasks,
+        time::Duration,
+        timers::{millis, virtual_clock},
+    };
+
+    #[test]
+    fn permits_limit_the_tasks_at_once() {
+        const TASKS: usize = 4;
+        let _clock = virtual_clock::lock_for_test();
+        let semaphore = Semaphore::new(2);
+        let starts = RefCell::new(std::vec::Vec::new());
+        let tasks = array::from_fn::<_, TASKS, _>(|_| async {
+            let _permit = semaphore.acquire().await;
+            starts.borrow_mut().push(millis());
+            Delay::wait_for(Duration::from_millis(10)).await;
+        });
+        block_on_with_tasks(tasks, async {});
+        assert_eq!(*starts.borrow(), [0, 0, 10, 10]);
+        assert_eq!(semaphore.available(), 2);
+    }
+
+    #[test]
+    fn forgotten_permits_are_released_by_hand() {
+        let semaphore = Semaphore::new(1);
+        semaphore.try_acquire().unwrap().forget();
+        assert!(semaphore.try_acquire().is_none());
+        semaphore.release();
+        let permit = semaphore.try_acquire();
+        assert!(permit.is_some());
+        assert_eq!(semaphore.available(), 0);
+        drop(permit);
+        assert_eq!(semaphore.available(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+//! One-slot signal that an interrupt handler sets and a task waits for.
+//!
+//! Setting the signal again before the task took it replaces the value, only
+//! the latest one is kept.
+//!
+//! ```
+//! static LIMIT_SWITCH: Signal<Direction> = Signal::new();
+//!
+//! #[avr_device::interrupt(atmega328p)]
+//! fn PCINT0() {
+//!     LIMIT_SWITCH.signal(Direction::Forward);
+//! }
+//!
+//! // motor task
+//! let direction = LIMIT_SWITCH.wait().await;
+//! ```
+
+use core::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use crate::backend::{free, Mutex};
+
+struct State<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+}
+
+pub struct Signal<T> {
+    state: Mutex<RefCell<State<T>>>,
+}
+
+impl<T> Signal<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(State {
+                value: None,
+                waker: None,
+            })),
+        }
+    }
+
+    /// Sets the signal to `value` and wakes the waiting task. Can be called
+    /// from interrupt handlers.
+    pub fn signal(&self, value: T) {
+        let waker = free(|cs| {
+            let mut state = self.state.borrow(cs).borrow_mut();
+            state.value = Some(value);
+            state.waker.take()
+        });
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Clears the signal, so that [`wait`](Self::wait) only returns values
+    /// set from now on.
+    pub fn reset(&self) {
+        free(|cs| self.state.borrow(cs).borrow_mut().value = None);
+    }
+
+    /// Takes the value if the signal is set.
+    #[allow(dead_code)]
+    pub fn try_take(&self) -> Option<T> {
+        free(|cs| self.state.borrow(cs).borrow_mut().value.take())
+    }
+
+    /// Whether the signal is set, without taking the value.
+    #[allow(dead_code)]
+    pub fn signaled(&self) -> bool {
+        free(|cs| self.state.borrow(cs).borrow().value.is_some())
+    }
+
+    /// Waits until the signal is set and takes the value. Only one task may
+    /// wait at a time.
+    pub fn wait(&self) -> WaitFuture<'_, T> {
+        WaitFuture { signal: self }
+    }
+}
+
+/// Future for [`Signal::wait`]
+pub struct WaitFuture<'a, T> {
+    signal: &'a Signal<T>,
+}
+
+impl<T> Future for WaitFuture<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        free(|cs| {
+            let mut state = self.signal.state.borrow(cs).borrow_mut();
+            match state.value.take() {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    match &state.waker {
+                        Some(waker) if waker.will_wake(cx.waker()) => {}
+                        _ => state.waker = Some(cx.waker().clone()),
+                    }
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        futures::{delay::Delay, join::join},
+        simulation::block_on,
+        time::Duration,
+        timers::virtual_clock,
+    };
+
+    #[test]
+    fn the_latest_value_wins() {
+        let _clock = virtual_clock::lock_for_test();
+        let signal = Signal::new();
+        signal.signal(1);
+        signal.signal(2);
+        assert_eq!(block_on(signal.wait()), 2);
+        assert!(!signal.signaled());
+    }
+
+    #[test]
+    fn reset_drops_the_value() {
+        let _clock = virtual_clock::lock_for_test();
+        let signal = Signal::new();
+        signal.signal(1);
+        signal.reset();
+        assert!(!signal.signaled());
+        let set_later = async {
+            Delay::wait_for(Duration::from_millis(10)).await;
+            signal.signal(2);
+        };
+        let (_, value) = block_on(join(set_later, signal.wait()));
+        assert_eq!(value, 2);
+    }
+}
@@ -0,0 +1,179 @@
+//! Periodic timer that does not drift.
+//!
+//! ```
+//! let mut ticker = Ticker::every(Duration::from_millis(100));
+//! loop {
+//!     control_step();
+//!     ticker.next().await;
+//! }
+//! ```
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    futures::delay::Delay,
+    time::{Duration, Instant},
+};
+
+/// What to do when a tick was missed because the loop took longer than the
+/// period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTickPolicy {
+    /// Fire the missed ticks right away until the ticker caught up
+    Burst,
+    /// Fire once right away and continue on the original schedule
+    Skip,
+    /// Fire once right away and schedule the next ticks from now on
+    Delay,
+}
+
+pub struct Ticker {
+    period: Duration,
+    next: Instant,
+    policy: MissedTickPolicy,
+    overruns: u32,
+    /// Latest tick that was counted in `overruns`, so that a tick is counted
+    /// once even if it fires late in a burst
+    counted_until: Instant,
+}
+
+impl Ticker {
+    /// Ticks every `period`, starting one period from now.
+    pub fn every(period: Duration) -> Self {
+        assert!(period > Duration::ZERO);
+        let now = Instant::now();
+        Self {
+            period,
+            next: now + period,
+            policy: MissedTickPolicy::Burst,
+            overruns: 0,
+            counted_until: now,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: MissedTickPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Number of ticks that were missed so far
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+
+    /// Starts over, with the next tick one period from now.
+    pub fn reset(&mut self) {
+        self.next = Instant::now() + self.period;
+    }
+
+    /// Waits for the next tick. The ticker only moves on when the tick has
+    /// fired, so a tick that is dropped before, e.g. in a `select`, is not
+    /// lost.
+    pub fn next(&mut self) -> Tick<'_> {
+        let now = Instant::now();
+        let missed = now.duration_since(self.next).as_millis() / self.period.as_millis();
+        if missed > 0 {
+            let last_missed = self.next + self.period * missed;
+            let counted = if self.counted_until < self.next {
+                self.next
+            } else {
+                self.counted_until
+            };
+            let new = last_missed.duration_since(counted).as_millis() / self.period.as_millis();
+            self.overruns = self.overruns.saturating_add(new);
+            self.counted_until = last_missed;
+            match self.policy {
+                MissedTickPolicy::Burst => {}
+                MissedTickPolicy::Skip => self.next = last_missed,
+                MissedTickPolicy::Delay => self.next = now,
+            }
+        }
+        Tick {
+            delay: Delay::until(self.next),
+            ticker: self,
+        }
+    }
+}
+
+/// Future for [`Ticker::next`]
+pub struct Tick<'a> {
+    ticker: &'a mut Ticker,
+    delay: Delay,
+}
+
+impl Future for Tick<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let delay = unsafe { Pin::new_unchecked(&mut this.delay) };
+        if delay.poll(cx).is_ready() {
+            this.ticker.next += this.ticker.period;
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        futures::select::select,
+        simulation::block_on,
+        timers::{millis, virtual_clock},
+    };
+
+    /// Runs a ticker that starts 350 ms late and returns the times of the
+    /// first `ticks` ticks and the overruns.
+    fn late_ticks(policy: MissedTickPolicy, ticks: usize) -> (std::vec::Vec<u32>, u32) {
+        let mut ticker = Ticker::every(Duration::from_millis(100)).with_policy(policy);
+        virtual_clock::advance(350);
+        let mut times = std::vec::Vec::new();
+        block_on(async {
+            for _ in 0..ticks {
+                ticker.next().await;
+                times.push(millis());
+            }
+        });
+        (times, ticker.overruns())
+    }
+
+    #[test]
+    fn burst_fires_the_missed_ticks_and_counts_them_once() {
+        let _clock = virtual_clock::lock_for_test();
+        assert_eq!(late_ticks(MissedTickPolicy::Burst, 4), (vec![350, 350, 350, 400], 2));
+    }
+
+    #[test]
+    fn skip_keeps_the_schedule() {
+        let _clock = virtual_clock::lock_for_test();
+        assert_eq!(late_ticks(MissedTickPolicy::Skip, 3), (vec![350, 400, 500], 2));
+    }
+
+    #[test]
+    fn delay_moves_the_schedule() {
+        let _clock = virtual_clock::lock_for_test();
+        assert_eq!(late_ticks(MissedTickPolicy::Delay, 3), (vec![350, 450, 550], 2));
+    }
+
+    #[test]
+    fn cancelled_tick_is_not_lost() {
+        let _clock = virtual_clock::lock_for_test();
+        let mut ticker = Ticker::every(Duration::from_millis(100));
+        block_on(async {
+            select(Delay::wait_for(Duration::from_millis(50)), ticker.next()).await;
+            assert_eq!(millis(), 50);
+            ticker.next().await;
+            assert_eq!(millis(), 100);
+            ticker.next().await;
+            assert_eq!(millis(), 200);
+        });
+        assert_eq!(ticker.overruns(), 0);
+    }
+}
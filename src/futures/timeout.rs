@@ -0,0 +1,99 @@
+//! Bound the time a future may take.
+//!
+//! ```
+//! match with_timeout(Duration::from_secs(5), wait_for_button()).await {
+//!     Ok(()) => start_motor(),
+//!     Err(TimeoutError) => show_idle_screen(),
+//! }
+//! ```
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    futures::delay::Delay,
+    time::{Duration, Instant},
+};
+
+/// The future did not complete in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutError;
+
+/// Runs `future` for at most `timeout`.
+pub fn with_timeout<F: Future>(timeout: Duration, future: F) -> Timeout<F> {
+    with_deadline(Instant::now() + timeout, future)
+}
+
+/// Runs `future` until `deadline` at most.
+pub fn with_deadline<F: Future>(deadline: Instant, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        delay: Delay::until(deadline),
+    }
+}
+
+/// Future for [`with_timeout`] and [`with_deadline`]. On a timeout, the inner
+/// future is dropped together with the `Timeout`, and the timer is removed
+/// from the queue once the `Timeout` is dropped.
+pub struct Timeout<F> {
+    future: F,
+    delay: Delay,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, TimeoutError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        let delay = unsafe { Pin::new_unchecked(&mut this.delay) };
+        if delay.poll(cx).is_ready() {
+            return Poll::Ready(Err(TimeoutError));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::free,
+        simulation::block_on,
+        timers::{millis, virtual_clock, WAKERS},
+    };
+
+    fn next_wake_time() -> Option<Instant> {
+        free(|cs| WAKERS.borrow(cs).borrow().next_wake_time())
+    }
+
+    #[test]
+    fn returns_the_output_if_the_future_is_in_time() {
+        let _clock = virtual_clock::lock_for_test();
+        let output = block_on(with_timeout(Duration::from_millis(100), async {
+            Delay::wait_for(Duration::from_millis(10)).await;
+            42
+        }));
+        assert_eq!(output, Ok(42));
+        assert_eq!(millis(), 10);
+        assert_eq!(next_wake_time(), None);
+    }
+
+    #[test]
+    fn times_out_and_drops_the_timers_of_the_future() {
+        let _clock = virtual_clock::lock_for_test();
+        let output = block_on(with_timeout(Duration::from_millis(10), async {
+            Delay::wait_for(Duration::from_millis(100)).await;
+            42
+        }));
+        assert_eq!(output, Err(TimeoutError));
+        assert_eq!(millis(), 10);
+        assert_eq!(next_wake_time(), None);
+    }
+}
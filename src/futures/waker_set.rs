@@ -0,0 +1,124 @@
+//! Wakers of the tasks that wait for a shared resource, e.g. for room in a
+//! [`Channel`](super::channel::Channel).
+//!
+//! Every waiting future embeds a [`WaitNode`] that is linked into the set of
+//! the resource, so any number of tasks can wait without waking each other.
+//! When the resource is freed, all of them are woken and check it again, and
+//! the ones that still have to wait link their node again. A future that
+//! links its node has to unlink it when it is dropped.
+//!
+//! ```
+//! // on a failed attempt
+//! state.waiters.register(node, cx.waker());
+//!
+//! // when the resource is freed
+//! state.waiters.wake_all();
+//!
+//! // when the future is dropped
+//! state.waiters.remove(&self.node);
+//! ```
+
+use core::{
+    cell::{Cell, RefCell},
+    marker::PhantomPinned,
+    pin::Pin,
+    ptr,
+    task::Waker,
+};
+
+/// A task waiting in a [`WakerSet`].
+///
+/// The node lives in the future that waits, e.g. a
+/// [`LockFuture`](super::mutex::LockFuture). Once linked, the node must stay
+/// at its address, which pinning the future guarantees, and the future must
+/// remove it from the set before it is dropped. All fields are only accessed
+/// in a critical section.
+pub struct WaitNode {
+    waker: RefCell<Option<Waker>>,
+    next: Cell<*const WaitNode>,
+    linked: Cell<bool>,
+    _pinned: PhantomPinned,
+}
+
+impl WaitNode {
+    pub const fn new() -> Self {
+        Self {
+            waker: RefCell::new(None),
+            next: Cell::new(ptr::null()),
+            linked: Cell::new(false),
+            _pinned: PhantomPinned,
+        }
+    }
+}
+
+/// Singly linked list of the waiting tasks, the one that waits longest first
+pub struct WakerSet {
+    head: Cell<*const WaitNode>,
+}
+
+// The nodes are only reached through the set while the critical section is
+// held.
+unsafe impl Send for WakerSet {}
+
+impl WakerSet {
+    pub const fn new() -> Self {
+        Self {
+            head: Cell::new(ptr::null()),
+        }
+    }
+
+    /// Links `node` to wake `waker`, or updates its waker if it is already
+    /// linked.
+    pub fn register(&mut self, node: Pin<&WaitNode>, waker: &Waker) {
+        let node = node.get_ref();
+        let mut current = node.waker.borrow_mut();
+        match &*current {
+            Some(w) if w.will_wake(waker) => {}
+            _ => *current = Some(waker.clone()),
+        }
+        if node.linked.get() {
+            return;
+        }
+        let mut link = &self.head;
+        while let Some(next) = unsafe { link.get().as_ref() } {
+            link = &next.next;
+        }
+        link.set(node);
+        node.linked.set(true);
+    }
+
+    /// Unlinks `node` if it is linked, e.g. of a dropped future.
+    pub fn remove(&mut self, node: &WaitNode) {
+        if !node.linked.get() {
+            return;
+        }
+        let mut link = &self.head;
+        while let Some(next) = unsafe { link.get().as_ref() } {
+            if ptr::eq(next, node) {
+                link.set(node.next.get());
+                node.next.set(ptr::null());
+                node.linked.set(false);
+                node.waker.borrow_mut().take();
+                return;
+            }
+            link = &next.next;
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.head.get().is_null()
+    }
+
+    /// Wakes all tasks in the set and empties it.
+    pub fn wake_all(&mut self) {
+        while let Some(node) = unsafe { self.head.get().as_ref() } {
+            self.head.set(node.next.get());
+            node.next.set(ptr::null());
+            node.linked.set(false);
+            if let Some(waker) = node.waker.borrow_mut().take() {
+                waker.wake();
+            }
+        }
+    }
+}
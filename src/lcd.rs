@@ -1,5 +1,5 @@
 
-use core::cell::RefCell;
+use core::cell::Cell;
 use core::ops::Coroutine;
 use core::iter;
 
@@ -9,6 +9,13 @@ use embedded_hal::digital::v2::OutputPin;
 use heapless::String;
 
 use crate::futures::delay::Delay;
+use crate::futures::mutex::Mutex;
+use crate::futures::semaphore::Semaphore;
+use crate::futures::ticker::Ticker;
+use crate::rtc;
+use crate::sleep;
+use crate::time::Duration;
+use crate::watchdog;
 
 const DISPLAY_WIDTH: usize = 16;
 
@@ -34,19 +41,190 @@ fn generate_moving_text(text: &str) -> impl Coroutine<Yield = String<DISPLAY_WID
     }
 }
 
-pub async fn show_moving_text<T, D>(text: (&str, &str), lcd: &RefCell<LcdDisplay<T, D>>)
+/// Writes both lines of the display, holding a permit of `bus` while the
+/// display is written.
+async fn print_lines<T, D>(lcd: &Mutex<LcdDisplay<T, D>>, bus: &Semaphore, l1: &str, l2: &str)
 where
     T: OutputPin + Sized,
     D: DelayUs<u16> + Sized,
+{
+    let _permit = bus.acquire().await;
+    // The TWI does not wake the board from the deeper sleep modes.
+    let _awake = sleep::stay_awake();
+    let mut lcd = lcd.lock().await;
+    lcd.set_position(0, 0).await;
+    lcd.print(l1).await;
+    lcd.set_position(0, 1).await;
+    lcd.print(l2).await;
+}
+
+/// `bus` is shared with the other devices on the I2C bus of the display.
+pub async fn show_moving_text<T, D>(
+    text: (&str, &str),
+    lcd: &Mutex<LcdDisplay<T, D>>,
+    bus: &Semaphore,
+) where
+    T: OutputPin + Sized,
+    D: DelayUs<u16> + Sized,
 {
     let line1 = iter::from_coroutine(generate_moving_text(text.0));
     let line2 = iter::from_coroutine(generate_moving_text(text.1));
     for (l1, l2) in iter::zip(line1, line2) {
-        let mut lcd = lcd.borrow_mut();
-        lcd.set_position(0, 0).await;
-        lcd.print(&l1).await;
-        lcd.set_position(0, 1).await;
-        lcd.print(&l2).await;
-        Delay::wait_for(500).await;
+        print_lines(lcd, bus, &l1, &l2).await;
+        watchdog::check_in();
+        Delay::wait_for(Duration::from_millis(500)).await;
+    }
+}
+
+/// Pads `text` with spaces to the width of the display.
+fn full_line(text: &str) -> String<DISPLAY_WIDTH> {
+    let mut line = String::new();
+    let _ = line.push_str(text);
+    while line.push(' ').is_ok() {}
+    line
+}
+
+/// Formats `n` in decimal, without pulling in `core::fmt`.
+fn number(n: i32) -> String<DISPLAY_WIDTH> {
+    let mut digits: String<DISPLAY_WIDTH> = String::new();
+    let mut rest = n.unsigned_abs();
+    loop {
+        let _ = digits.push((b'0' + (rest % 10) as u8) as char);
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        let _ = digits.push('-');
+    }
+    digits.chars().rev().collect()
+}
+
+/// Shows the date and time in UTC for `duration`, updated every second.
+pub async fn show_clock<T, D>(lcd: &Mutex<LcdDisplay<T, D>>, bus: &Semaphore, duration: Duration)
+where
+    T: OutputPin + Sized,
+    D: DelayUs<u16> + Sized,
+{
+    let mut ticker = Ticker::every(Duration::from_secs(1));
+    for _ in 0..duration.as_millis() / 1000 {
+        let (l1, l2) = match rtc::now() {
+            Some(now) => {
+                let mut time: String<DISPLAY_WIDTH> = String::new();
+                let _ = time.push_str(&now.format_time());
+                let _ = time.push_str(" UTC");
+                (full_line(&now.format_date()), full_line(&time))
+            }
+            None => (full_line("Time not set"), full_line("")),
+        };
+        print_lines(lcd, bus, &l1, &l2).await;
+        watchdog::check_in();
+        ticker.next().await;
+    }
+}
+
+/// Shows the motor position for `duration`, updated every 250 ms.
+pub async fn show_position<T, D>(
+    lcd: &Mutex<LcdDisplay<T, D>>,
+    bus: &Semaphore,
+    position: &Cell<i32>,
+    duration: Duration,
+) where
+    T: OutputPin + Sized,
+    D: DelayUs<u16> + Sized,
+{
+    let mut ticker = Ticker::every(Duration::from_millis(250));
+    for _ in 0..duration.as_millis() / 250 {
+        let mut steps = number(position.get());
+        let _ = steps.push_str(" steps");
+        print_lines(lcd, bus, &full_line("Motor position"), &full_line(&steps)).await;
+        watchdog::check_in();
+        ticker.next().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{cell::RefCell, convert::Infallible, pin::pin};
+
+    use super::*;
+    use crate::{
+        ag_lcd::Lines,
+        futures::select::select,
+        simulation::block_on,
+        timers::{millis, virtual_clock},
+    };
+
+    /// Records the virtual time of its rising edges, if it has a log
+    struct LcdPin<'a> {
+        rising: Option<&'a RefCell<std::vec::Vec<u32>>>,
+    }
+
+    impl OutputPin for LcdPin<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            if let Some(rising) = self.rising {
+                rising.borrow_mut().push(millis());
+            }
+            Ok(())
+        }
+    }
+
+    /// The blocking delays of the display driver don't take virtual time
+    struct NoDelay;
+
+    impl DelayUs<u16> for NoDelay {
+        fn delay_us(&mut self, _us: u16) {}
+    }
+
+    #[test]
+    fn moving_text_bounces_between_the_edges() {
+        let frames: std::vec::Vec<String<DISPLAY_WIDTH>> =
+            iter::from_coroutine(generate_moving_text("ab")).take(29).collect();
+        assert_eq!(frames[0], "ab              ");
+        assert_eq!(frames[1], " ab             ");
+        assert_eq!(frames[14], "              ab");
+        assert_eq!(frames[15], "             ab ");
+        assert_eq!(frames[27], " ab             ");
+        assert_eq!(frames[28], frames[0]);
+    }
+
+    #[test]
+    fn moving_text_waits_half_a_second_between_frames() {
+        let _clock = virtual_clock::lock_for_test();
+        let pulses = RefCell::new(std::vec::Vec::new());
+        let idle = || LcdPin { rising: None };
+        let enable = LcdPin { rising: Some(&pulses) };
+        let lcd = LcdDisplay::new(idle(), enable, NoDelay)
+            .with_half_bus(idle(), idle(), idle(), idle())
+            .with_lines(Lines::TwoLines)
+            .build();
+        pulses.borrow_mut().clear();
+        let lcd = Mutex::new(lcd);
+        let bus = Semaphore::new(1);
+
+        block_on(select(
+            pin!(show_moving_text(("ab", "cd"), &lcd, &bus)),
+            pin!(Delay::wait_for(Duration::from_millis(1500))),
+        ));
+
+        // Each frame is written in one burst of enable pulses.
+        let pulses = pulses.into_inner();
+        let mut frames = std::vec::Vec::new();
+        for (i, &time) in pulses.iter().enumerate() {
+            if i == 0 || time - pulses[i - 1] > 100 {
+                frames.push((time, time));
+            }
+            frames.last_mut().unwrap().1 = time;
+        }
+        // Writing a frame takes 40 ms on the virtual clock, the delays of
+        // the commands and characters are rounded up to whole milliseconds.
+        assert_eq!(frames, [(0, 40), (540, 580), (1080, 1120)]);
     }
 }
@@ -1,5 +1,7 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(target_arch = "avr", no_std)]
+#![cfg_attr(target_arch = "avr", no_main)]
+// Parts of the firmware are only used on the board, not on the host.
+#![cfg_attr(not(target_arch = "avr"), allow(dead_code))]
 #![feature(abi_avr_interrupt)]
 #![feature(coroutines)]
 #![feature(coroutine_trait)]
@@ -8,44 +10,119 @@
 #![feature(panic_info_message)]
 
 mod ag_lcd;
+mod backend;
 mod blinks;
+#[cfg(target_arch = "avr")]
+mod calibration;
+mod config;
+#[cfg(target_arch = "avr")]
+mod console;
 mod executor;
+#[cfg(target_arch = "avr")]
 mod freq_pin;
 mod futures;
 mod lcd;
+mod rtc;
+#[cfg(not(target_arch = "avr"))]
+mod simulation;
+mod sleep;
+mod stepper;
+mod time;
 mod timers;
+mod watchdog;
 
-use core::{cell::RefCell, panic::PanicInfo};
+#[cfg(target_arch = "avr")]
+use core::{
+    cell::{Cell, RefCell},
+    panic::PanicInfo,
+    pin::pin,
+};
 
+#[cfg(target_arch = "avr")]
 use crate::{
     blinks::{pulse, sos},
-    executor::Executor,
+    executor::{Executor, InterruptExecutor},
     freq_pin::{Timer2Freq, FreqPinPD3},
-    futures::{delay::Delay, join::join4},
+    futures::{
+        channel::Channel,
+        delay::Delay,
+        event_group::EventGroup,
+        join::{join, join4},
+        mutex::Mutex,
+        semaphore::Semaphore,
+        ticker::Ticker,
+        timeout::with_timeout,
+    },
+    rtc::ds3231::{self, Ds3231},
+    time::Duration,
+    stepper::{MotorCommand, Stepper},
     timers::millis_init,
 };
 
+#[cfg(target_arch = "avr")]
 use ag_lcd::{Cursor, LcdDisplay, Lines};
+#[cfg(target_arch = "avr")]
 use arduino_hal::simple_pwm::{IntoPwmPin, Prescaler, Timer1Pwm};
+#[cfg(target_arch = "avr")]
 use port_expander::dev::pcf8574::Pcf8574;
 
+#[cfg(target_arch = "avr")]
 type Serial = arduino_hal::Usart<
     arduino_hal::pac::USART0,
     arduino_hal::port::Pin<arduino_hal::port::mode::Input, arduino_hal::hal::port::PD0>,
     arduino_hal::port::Pin<arduino_hal::port::mode::Output, arduino_hal::hal::port::PD1>,
 >;
 
+/// Runs the motor control, preempting the UI tasks on the thread-mode executor
+#[cfg(target_arch = "avr")]
+static MOTOR_EXECUTOR: InterruptExecutor<1> = InterruptExecutor::new();
+
+/// Commands from the buttons to the motor task
+#[cfg(target_arch = "avr")]
+static MOTOR_COMMANDS: Channel<MotorCommand, 4> = Channel::new();
+
+/// Position updates from the motor task to the display
+#[cfg(target_arch = "avr")]
+static MOTOR_POSITIONS: Channel<i32, 4> = Channel::new();
+
+/// Conditions the manual control waits for after a reset
+#[cfg(target_arch = "avr")]
+static STARTUP: EventGroup = EventGroup::new();
+#[cfg(target_arch = "avr")]
+const CONFIG_LOADED: u8 = 1 << 0;
+#[cfg(target_arch = "avr")]
+const DISPLAY_READY: u8 = 1 << 1;
+#[cfg(target_arch = "avr")]
+const MOTOR_READY: u8 = 1 << 2;
+
+#[cfg(target_arch = "avr")]
+#[avr_device::interrupt(atmega328p)]
+fn EE_READY() {
+    unsafe { MOTOR_EXECUTOR.on_interrupt() }
+}
+
+#[cfg(target_arch = "avr")]
 static mut SERIAL_PTR: *mut Serial = core::ptr::null_mut();
 
+#[cfg(target_arch = "avr")]
 macro_rules! dbgprint {
     ($($args:expr),*) => {{
         unsafe { ufmt::uwriteln!(&mut *crate::SERIAL_PTR, $($args),*).unwrap(); }
     }};
 }
+#[cfg(not(target_arch = "avr"))]
+macro_rules! dbgprint {
+    ($($args:expr),*) => {{
+        std::println!($($args),*);
+    }};
+}
 pub(crate) use dbgprint;
 
+#[cfg(target_arch = "avr")]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Stop the motor first, printing the message may fail as well.
+    safe_state();
     if let Some(location) = info.location() {
         dbgprint!("panic occurred in file '{}' at line {}", location.file(), location.line());
         if let Some(msg) = info.message() {
@@ -63,38 +140,72 @@ fn panic(info: &PanicInfo) -> ! {
     loop {}
 }
 
+/// Shutdown hook of the executor and first action of the panic handler,
+/// disables the motor driver and switches the LEDs off.
+#[cfg(target_arch = "avr")]
+fn safe_state() {
+    // The pins belong to tasks that are no longer running.
+    let dp = unsafe { arduino_hal::Peripherals::steal() };
+    dp.TC2.tccr2a.modify(|_r, w| w.com2b().disconnected());
+    dp.TC1.tccr1a.modify(|_r, w| w.com1a().disconnected());
+    // d8 high disables the motors, the LEDs are on d9 and d13.
+    dp.PORTB.portb.modify(|_r, w| w.pb0().set_bit().pb1().clear_bit().pb5().clear_bit());
+    dp.PORTD.portd.modify(|_r, w| w.pd3().clear_bit());
+}
+
+#[cfg(not(target_arch = "avr"))]
+fn main() {
+    simulation::run();
+}
+
+#[cfg(target_arch = "avr")]
 #[arduino_hal::entry]
 fn main() -> ! {
     let dp = arduino_hal::Peripherals::take().unwrap();
     let pins = arduino_hal::pins!(dp);
 
     let mut serial = arduino_hal::default_serial!(dp, pins, 57600);
+    serial.listen(arduino_hal::hal::usart::Event::RxComplete);
     unsafe {
         SERIAL_PTR = &mut serial;
     }
     ufmt::uwriteln!(&mut serial, "Booting up").unwrap();
+    watchdog::report_last_reset();
 
     let delay = arduino_hal::Delay::new();
 
     let sda = pins.a4.into_pull_up_input();
     let scl = pins.a5.into_pull_up_input();
 
-    let i2c_bus = arduino_hal::i2c::I2c::new(dp.TWI, sda, scl, 50000);
-    let mut i2c_expander = Pcf8574::new(i2c_bus, true, true, true);
+    let i2c = arduino_hal::i2c::I2c::new(dp.TWI, sda, scl, 50000);
+    let i2c_bus = shared_bus::BusManagerSimple::new(i2c);
+    let mut i2c_expander = Pcf8574::new(i2c_bus.acquire_i2c(), true, true, true);
+    let ds3231 = RefCell::new(Ds3231::new(i2c_bus.acquire_i2c()));
+    // Each I2C transfer is atomic, a permit keeps the transfers of one device
+    // together.
+    let i2c_access = Semaphore::new(1);
 
     let lcd: LcdDisplay<_, _> = LcdDisplay::new_pcf8574(&mut i2c_expander, delay)
         .with_cursor(Cursor::Off)
         .with_lines(Lines::TwoLines)
         .build();
-    let lcd = RefCell::new(lcd);
+    let lcd = Mutex::new(lcd);
 
-    // Configure INT0 for rising edge. 0x02 would be falling edge.
+    // Configure INT0 for rising edge. 0x02 would be falling edge. The 1PPS
+    // signal for the calibration comes in here.
     dp.EXINT.eicra.modify(|_, w| w.isc0().bits(0x03));
     // Enable the INT0 interrupt source.
     dp.EXINT.eimsk.modify(|_, w| w.int0().set_bit());
 
     ufmt::uwriteln!(&mut serial, "A").unwrap();
     millis_init(&dp.TC0);
+    watchdog::start(&dp.WDT, &dp.CPU);
+    calibration::load();
+    STARTUP.set(CONFIG_LOADED);
+    match ds3231::sync(&mut ds3231.borrow_mut()) {
+        Ok(time) => dbgprint!("time from DS3231: {}", time.format().as_str()),
+        Err(_) => dbgprint!("no time from DS3231, set it with: time YYYY-MM-DD HH:MM:SS"),
+    }
 
     let timer1 = Timer1Pwm::new(dp.TC1, Prescaler::Prescale64);
     let mut pwm_led = pins.d9.into_output().into_pwm(&timer1);
@@ -103,13 +214,15 @@ fn main() -> ! {
 
     let mut direction = pins.d6.into_output();
     direction.set_high();
-    let mut enable_motors = pins.d8.into_output();
-    enable_motors.set_high();
+    let enable_motors = pins.d8.into_output();
     let timer2 = Timer2Freq::new(dp.TC2, Prescaler::Prescale256);
-    let mut steps = FreqPinPD3::new(&timer2, pins.d3.into_output());
+    let steps = FreqPinPD3::new(timer2, pins.d3.into_output());
+    let mut motor = Stepper::new(enable_motors, direction, steps);
 
     let button1 = pins.d11.into_pull_up_input();
     let button2 = pins.d10.into_pull_up_input();
+    // d11 and d10 are PB3 and PB2.
+    sleep::wake_on_pin_change(1 << 3 | 1 << 2);
 
     ufmt::uwriteln!(&mut serial, "B").unwrap();
     dbgprint!("ABC");
@@ -117,43 +230,101 @@ fn main() -> ! {
     unsafe { avr_device::interrupt::enable() };
 
     ufmt::uwriteln!(&mut serial, "C").unwrap();
-    let executor = Executor::new();
+    let executor = pin!(Executor::<3>::new().with_shutdown_hook(safe_state));
+    let executor = executor.into_ref();
 
-    executor.block_on(join4(
-        async {
+    executor
+        .spawn(async move {
             loop {
                 sos(&mut onboard_led).await;
             }
-        },
-        async {
-            Delay::wait_for(1000).await;
+        })
+        .unwrap();
+    executor
+        .spawn(async move {
+            Delay::wait_for(Duration::from_secs(1)).await;
             loop {
                 pulse(&mut pwm_led).await;
             }
-        },
-        async {
-            Delay::wait_for(2000).await;
-            lcd::show_moving_text(("Mag Loop", "Control"), &lcd).await;
-        },
-        async {
-            steps.set_freq(500);
-            loop {
-                if button1.is_low() {
-                    enable_motors.set_low();
-                    direction.set_high();
-                    steps.enable();
-                } else if button2.is_low() {
-                    enable_motors.set_low();
-                    direction.set_low();
-                    steps.enable();
-                } else {
-                    enable_motors.set_high();
-                    steps.disable();
+        })
+        .unwrap();
+    let lcd_task = executor
+        .spawn(async {
+            let position = Cell::new(0);
+            let screens = async {
+                Delay::wait_for(Duration::from_secs(2)).await;
+                STARTUP.set(DISPLAY_READY);
+                loop {
+                    let text = lcd::show_moving_text(("Mag Loop", "Control"), &lcd, &i2c_access);
+                    let _ = with_timeout(Duration::from_secs(20), text).await;
+                    lcd::show_clock(&lcd, &i2c_access, Duration::from_secs(10)).await;
+                    lcd::show_position(&lcd, &i2c_access, &position, Duration::from_secs(5)).await;
+                }
+            };
+            let positions = async {
+                loop {
+                    position.set(MOTOR_POSITIONS.recv().await);
                 }
-                Delay::wait_for(100).await;
+            };
+            join(screens, positions).await;
+        })
+        .unwrap();
+    watchdog::watch(&lcd_task, Duration::from_secs(5)).unwrap();
+    let motor_task = MOTOR_EXECUTOR
+        .spawn(async move {
+            let commands = MOTOR_COMMANDS.receiver();
+            STARTUP.set(MOTOR_READY);
+            let control = stepper::motor_control(&mut motor, commands, MOTOR_POSITIONS.sender());
+            if control.await.is_err() {
+                dbgprint!("step frequency out of range");
             }
-        },
-    ));
+        })
+        .unwrap();
+    watchdog::watch(&motor_task, Duration::from_secs(1)).unwrap();
 
-    loop {}
+    let buttons = async {
+        STARTUP.wait_all(CONFIG_LOADED | DISPLAY_READY | MOTOR_READY).await;
+        dbgprint!("ready");
+        stepper::button_control(&button1, &button2, MOTOR_COMMANDS.sender()).await
+    };
+    let stats = async {
+        loop {
+            Delay::wait_for(Duration::from_secs(10)).await;
+            if let Some(now) = rtc::now() {
+                dbgprint!("{}", now.format().as_str());
+            }
+            executor.print_stats();
+            MOTOR_EXECUTOR.print_stats();
+        }
+    };
+    let console = async {
+        loop {
+            let line = console::read_line().await;
+            match line.split(' ').next() {
+                Some("time" | "trim") => {
+                    if let Some(time) = rtc::command(&line) {
+                        let _permit = i2c_access.acquire().await;
+                        if ds3231.borrow_mut().write(&time).is_err() {
+                            dbgprint!("could not set the DS3231");
+                        }
+                    }
+                }
+                Some("calibrate") => calibration::command(&line).await,
+                _ => dbgprint!("commands: time, trim, calibrate"),
+            }
+        }
+    };
+    // The DS3231 is more accurate than the crystal, if there is one.
+    let sync_rtc = async {
+        let mut ticker = Ticker::every(Duration::from_secs(3600));
+        loop {
+            ticker.next().await;
+            let _permit = i2c_access.acquire().await;
+            let _ = ds3231::sync(&mut ds3231.borrow_mut());
+        }
+    };
+    executor.run_forever(async {
+        let (never, _, _, _) = join4(stats, console, sync_rtc, buttons).await;
+        never
+    })
 }
@@ -0,0 +1,344 @@
+//! Software real-time clock keeping UTC date and time on top of [`millis`].
+//!
+//! The clock is unset after a reset until it is [`set`], either over the
+//! serial console or from a [`Ds3231`](ds3231::Ds3231). A trim in ppm makes
+//! up for the error of the crystal or resonator, e.g. `-20` if the clock
+//! runs 20 s per million seconds too fast.
+//!
+//! [`now`] has to be called at least once before `millis` wraps around,
+//! every 49 days.
+
+pub mod ds3231;
+
+use core::cell::Cell;
+
+use heapless::String;
+
+use crate::{
+    backend::{free, Mutex},
+    dbgprint,
+    timers::millis,
+};
+
+/// Valid trims, beyond this the clock source is broken anyway
+pub const MAX_TRIM_PPM: i16 = 1000;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Date and time in UTC, from 2000-01-01 to 2099-12-31
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// Checks the ranges of all fields, including the days of the month.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        let valid = (2000..=2099).contains(&year)
+            && (1..=12).contains(&month)
+            && day >= 1
+            && day <= days_in_month(year, month)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        valid.then_some(Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    /// Converts seconds since 1970-01-01 00:00:00, see
+    /// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    pub fn from_unix(secs: u32) -> Self {
+        let days = secs / 86400 + 719_468;
+        let secs_of_day = secs % 86400;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as u32;
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00:00, the inverse of [`from_unix`](Self::from_unix)
+    pub fn to_unix(self) -> u32 {
+        let month = self.month as u32;
+        let year = self.year as u32 - (month <= 2) as u32;
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * mp + 2) / 5 + self.day as u32 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        days * 86400 + self.hour as u32 * 3600 + self.minute as u32 * 60 + self.second as u32
+    }
+
+    /// Parses `YYYY-MM-DD HH:MM:SS`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.as_bytes();
+        let separators = [(4, b'-'), (7, b'-'), (10, b' '), (13, b':'), (16, b':')];
+        if s.len() != 19 || separators.iter().any(|&(i, c)| s[i] != c) {
+            return None;
+        }
+        let number = |range: core::ops::Range<usize>| {
+            s[range].iter().try_fold(0u16, |n, &c| {
+                c.is_ascii_digit().then(|| n * 10 + (c - b'0') as u16)
+            })
+        };
+        Self::new(
+            number(0..4)?,
+            number(5..7)? as u8,
+            number(8..10)? as u8,
+            number(11..13)? as u8,
+            number(14..16)? as u8,
+            number(17..19)? as u8,
+        )
+    }
+
+    /// `YYYY-MM-DD HH:MM:SS`, the format of [`parse`](Self::parse)
+    pub fn format(&self) -> String<19> {
+        let mut s = String::new();
+        let _ = s.push_str(&self.format_date());
+        let _ = s.push(' ');
+        let _ = s.push_str(&self.format_time());
+        s
+    }
+
+    /// `YYYY-MM-DD`
+    pub fn format_date(&self) -> String<10> {
+        let mut s = String::new();
+        push_digits(&mut s, self.year, 4);
+        let _ = s.push('-');
+        push_digits(&mut s, self.month as u16, 2);
+        let _ = s.push('-');
+        push_digits(&mut s, self.day as u16, 2);
+        s
+    }
+
+    /// `HH:MM:SS`
+    pub fn format_time(&self) -> String<8> {
+        let mut s = String::new();
+        push_digits(&mut s, self.hour as u16, 2);
+        let _ = s.push(':');
+        push_digits(&mut s, self.minute as u16, 2);
+        let _ = s.push(':');
+        push_digits(&mut s, self.second as u16, 2);
+        s
+    }
+}
+
+fn push_digits<const N: usize>(s: &mut String<N>, value: u16, digits: u32) {
+    for i in (0..digits).rev() {
+        let _ = s.push((b'0' + (value / 10u16.pow(i) % 10) as u8) as char);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Clock {
+    /// Seconds since 1970 at `last_millis`, `None` until the clock is set
+    unix: Option<u32>,
+    nanos: u32,
+    last_millis: u32,
+    trim_ppm: i16,
+}
+
+impl Clock {
+    /// Moves the clock forward to `now`, applying the trim.
+    fn update(&mut self, now: u32) {
+        let elapsed = now.wrapping_sub(self.last_millis);
+        self.last_millis = now;
+        let Some(unix) = self.unix else {
+            return;
+        };
+        // A millisecond has 1e6 ns, plus the trim in ppm.
+        let nanos = self.nanos as u64 + elapsed as u64 * (1_000_000 + self.trim_ppm as i32) as u64;
+        self.unix = Some(unix.wrapping_add((nanos / NANOS_PER_SECOND) as u32));
+        self.nanos = (nanos % NANOS_PER_SECOND) as u32;
+    }
+}
+
+static CLOCK: Mutex<Cell<Clock>> = Mutex::new(Cell::new(Clock {
+    unix: None,
+    nanos: 0,
+    last_millis: 0,
+    trim_ppm: 0,
+}));
+
+fn with_clock<R>(f: impl FnOnce(&mut Clock) -> R) -> R {
+    free(|cs| {
+        let cell = CLOCK.borrow(cs);
+        let mut clock = cell.get();
+        clock.update(millis());
+        let result = f(&mut clock);
+        cell.set(clock);
+        result
+    })
+}
+
+/// Sets the clock to `time`, at the start of that second.
+pub fn set(time: DateTime) {
+    with_clock(|clock| {
+        clock.unix = Some(time.to_unix());
+        clock.nanos = 0;
+    });
+}
+
+/// Whether the clock was set since the reset. It runs on `millis`, which
+/// keeps the board out of the deeper sleep modes from then on.
+pub fn is_set() -> bool {
+    free(|cs| CLOCK.borrow(cs).get().unix.is_some())
+}
+
+/// Current date and time, `None` if the clock was not set since the reset
+pub fn now() -> Option<DateTime> {
+    with_clock(|clock| clock.unix).map(DateTime::from_unix)
+}
+
+pub fn trim_ppm() -> i16 {
+    with_clock(|clock| clock.trim_ppm)
+}
+
+/// Sets the trim, clamped to [`MAX_TRIM_PPM`]. Positive values make the clock
+/// run faster.
+pub fn set_trim_ppm(ppm: i16) {
+    with_clock(|clock| clock.trim_ppm = ppm.clamp(-MAX_TRIM_PPM, MAX_TRIM_PPM));
+}
+
+/// Handles the console commands `time`, `time YYYY-MM-DD HH:MM:SS`, `trim` and
+/// `trim <ppm>`. Returns the new time if it was set, `None` for all other
+/// commands.
+pub fn command(line: &str) -> Option<DateTime> {
+    let (name, arg) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+    let arg = arg.trim();
+    match (name, arg) {
+        ("time", "") => match now() {
+            Some(time) => dbgprint!("{}", time.format().as_str()),
+            None => dbgprint!("time not set"),
+        },
+        ("time", arg) => match DateTime::parse(arg) {
+            Some(time) => {
+                set(time);
+                dbgprint!("time set to {}", time.format().as_str());
+                return Some(time);
+            }
+            None => dbgprint!("expected YYYY-MM-DD HH:MM:SS"),
+        },
+        ("trim", "") => dbgprint!("trim {} ppm", trim_ppm()),
+        ("trim", arg) => match arg.parse::<i16>() {
+            Ok(ppm) => {
+                set_trim_ppm(ppm);
+                dbgprint!("trim {} ppm", trim_ppm());
+            }
+            Err(_) => dbgprint!("expected the trim in ppm"),
+        },
+        _ => dbgprint!("unknown command"),
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timers::virtual_clock;
+
+    fn date_time(s: &str) -> DateTime {
+        DateTime::parse(s).unwrap()
+    }
+
+    #[test]
+    fn converts_known_dates() {
+        assert_eq!(DateTime::from_unix(946_684_800), date_time("2000-01-01 00:00:00"));
+        assert_eq!(DateTime::from_unix(951_782_400), date_time("2000-02-29 00:00:00"));
+        assert_eq!(DateTime::from_unix(1_709_251_199), date_time("2024-02-29 23:59:59"));
+        assert_eq!(DateTime::from_unix(4_102_444_799), date_time("2099-12-31 23:59:59"));
+    }
+
+    #[test]
+    fn unix_time_and_text_round_trip() {
+        // Just under a day apart, so every time of day and date comes up.
+        for secs in (946_684_800..=4_102_444_799).step_by(86_399) {
+            let time = DateTime::from_unix(secs);
+            assert_eq!(time.to_unix(), secs);
+            assert_eq!(DateTime::parse(&time.format()), Some(time));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_dates_and_text() {
+        assert!(DateTime::new(2000, 2, 29, 0, 0, 0).is_some());
+        assert!(DateTime::new(2100, 2, 28, 0, 0, 0).is_none());
+        assert!(DateTime::new(2023, 2, 29, 0, 0, 0).is_none());
+        assert!(DateTime::new(2023, 4, 31, 0, 0, 0).is_none());
+        assert!(DateTime::new(2023, 1, 1, 24, 0, 0).is_none());
+        for s in ["2024-1-01 00:00:00", "2024-01-01T00:00:00", "2024-01-01 00:00:0x", ""] {
+            assert_eq!(DateTime::parse(s), None, "{s}");
+        }
+    }
+
+    #[test]
+    fn trim_speeds_up_or_slows_down_the_clock() {
+        let mut clock = Clock {
+            unix: Some(0),
+            nanos: 0,
+            last_millis: 0,
+            trim_ppm: 100,
+        };
+        clock.update(1_000_000);
+        assert_eq!((clock.unix, clock.nanos), (Some(1000), 100_000_000));
+
+        clock = Clock {
+            unix: Some(0),
+            nanos: 0,
+            last_millis: u32::MAX - 499,
+            trim_ppm: -20,
+        };
+        // 10000 s across the wraparound of `millis`
+        clock.update(9_999_500);
+        assert_eq!((clock.unix, clock.nanos), (Some(9999), 800_000_000));
+    }
+
+    #[test]
+    fn runs_on_millis_once_set() {
+        let _clock = virtual_clock::lock_for_test();
+        set_trim_ppm(5000);
+        assert_eq!(trim_ppm(), MAX_TRIM_PPM);
+        set_trim_ppm(0);
+        set(date_time("2024-12-31 23:59:30"));
+        virtual_clock::advance(45_500);
+        assert_eq!(now(), Some(date_time("2025-01-01 00:00:15")));
+        assert!(is_set());
+    }
+}
@@ -0,0 +1,108 @@
+//! Driver for the DS3231 battery-backed RTC, which keeps the time while the
+//! board is off and drifts by about 2 ppm.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use super::DateTime;
+
+const ADDRESS: u8 = 0x68;
+const REG_SECONDS: u8 = 0x00;
+const REG_STATUS: u8 = 0x0f;
+/// Oscillator stop flag, set when the time was lost, e.g. on a flat battery
+const STATUS_OSF: u8 = 0x80;
+const HOUR_12H: u8 = 0x40;
+const HOUR_PM: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    I2c(E),
+    /// The oscillator stopped or the registers hold no valid date, the time
+    /// has to be set again
+    TimeLost,
+}
+
+pub struct Ds3231<I2C> {
+    i2c: I2C,
+}
+
+fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0x0f)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+impl<I2C, E> Ds3231<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c }
+    }
+
+    pub fn read(&mut self) -> Result<DateTime, Error<E>> {
+        let mut status = [0];
+        self.i2c.write_read(ADDRESS, &[REG_STATUS], &mut status).map_err(Error::I2c)?;
+        if status[0] & STATUS_OSF != 0 {
+            return Err(Error::TimeLost);
+        }
+
+        let mut regs = [0; 7];
+        self.i2c.write_read(ADDRESS, &[REG_SECONDS], &mut regs).map_err(Error::I2c)?;
+        let hour = if regs[2] & HOUR_12H != 0 {
+            let pm = if regs[2] & HOUR_PM != 0 { 12 } else { 0 };
+            from_bcd(regs[2] & 0x1f) % 12 + pm
+        } else {
+            from_bcd(regs[2] & 0x3f)
+        };
+        // regs[3] is the day of the week, the century bit in regs[5] is
+        // ignored as only 2000 to 2099 are supported.
+        DateTime::new(
+            2000 + from_bcd(regs[6]) as u16,
+            from_bcd(regs[5] & 0x1f),
+            from_bcd(regs[4] & 0x3f),
+            hour,
+            from_bcd(regs[1] & 0x7f),
+            from_bcd(regs[0] & 0x7f),
+        )
+        .ok_or(Error::TimeLost)
+    }
+
+    /// Sets the time in 24 hour mode and clears the oscillator stop flag.
+    pub fn write(&mut self, time: &DateTime) -> Result<(), Error<E>> {
+        // 1970-01-01 was a Thursday, the day of the week counts from 1.
+        let weekday = ((time.to_unix() / 86400 + 3) % 7 + 1) as u8;
+        self.i2c
+            .write(
+                ADDRESS,
+                &[
+                    REG_SECONDS,
+                    to_bcd(time.second),
+                    to_bcd(time.minute),
+                    to_bcd(time.hour),
+                    weekday,
+                    to_bcd(time.day),
+                    to_bcd(time.month),
+                    to_bcd((time.year - 2000) as u8),
+                ],
+            )
+            .map_err(Error::I2c)?;
+
+        let mut status = [0];
+        self.i2c.write_read(ADDRESS, &[REG_STATUS], &mut status).map_err(Error::I2c)?;
+        self.i2c
+            .write(ADDRESS, &[REG_STATUS, status[0] & !STATUS_OSF])
+            .map_err(Error::I2c)
+    }
+}
+
+/// Sets the software clock from `rtc`.
+pub fn sync<I2C, E>(rtc: &mut Ds3231<I2C>) -> Result<DateTime, Error<E>>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    let time = rtc.read()?;
+    super::set(time);
+    Ok(time)
+}
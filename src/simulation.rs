@@ -0,0 +1,96 @@
+//! Host entry point, running firmware tasks against the virtual clock.
+
+extern crate std;
+
+use core::{
+    convert::Infallible,
+    future::{poll_fn, Future},
+    pin::pin,
+    task::Poll,
+};
+
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::{blinks::sos, dbgprint, executor::Executor, timers::millis};
+
+/// Prints every change of its level together with the virtual time
+struct LoggingPin {
+    name: &'static str,
+}
+
+impl OutputPin for LoggingPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        dbgprint!("{:>6} ms: {} low", millis(), self.name);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        dbgprint!("{:>6} ms: {} high", millis(), self.name);
+        Ok(())
+    }
+}
+
+/// Blinks SOS on a simulated LED for ten seconds of virtual time.
+pub fn run() {
+    let mut led = LoggingPin { name: "led" };
+    let mut blinking = pin!(sos(&mut led));
+
+    let executor = pin!(Executor::<1>::new());
+    let executor = executor.into_ref();
+    executor.block_on(poll_fn(|cx| {
+        if millis() >= 10_000 {
+            return Poll::Ready(());
+        }
+        blinking.as_mut().poll(cx)
+    }));
+}
+
+/// Records every change of its level together with the virtual time
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingPin {
+    pub changes: std::vec::Vec<(u32, bool)>,
+}
+
+#[cfg(test)]
+impl OutputPin for RecordingPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.changes.push((millis(), false));
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.changes.push((millis(), true));
+        Ok(())
+    }
+}
+
+/// Runs `future` on a new executor until it completes.
+#[cfg(test)]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let executor = pin!(Executor::<1>::new());
+    executor.into_ref().block_on(future)
+}
+
+/// Spawns each of `tasks` on a new executor, so that every task has its own
+/// waker, and runs `future` next to them. Returns the output of `future` once
+/// the tasks have completed as well.
+#[cfg(test)]
+pub fn block_on_with_tasks<T, F, const N: usize>(tasks: [T; N], future: F) -> F::Output
+where
+    T: Future<Output = ()>,
+    F: Future,
+{
+    let executor = pin!(Executor::<N>::new());
+    let executor = executor.into_ref();
+    for task in tasks {
+        executor.spawn(task).unwrap();
+    }
+    let output = executor.block_on(future);
+    executor.run();
+    output
+}
@@ -0,0 +1,126 @@
+//! Sleep mode selection for the executor.
+//!
+//! Only Idle keeps Timer0, the USART, the TWI and the edge detection of INT0
+//! running. The deeper modes are used when none of them is needed:
+//!
+//! - no timer is pending and the software clock is not set, as both run on
+//!   Timer0 and [`millis`](crate::timers::millis) stops in the deeper modes
+//! - no [`StayAwake`] guard is held, e.g. by the console waiting for a line,
+//!   a calibration waiting for 1PPS edges or a transfer on the I2C bus
+//! - no PWM or step output is active, as they need the I/O clock
+//!
+//! Then only the pins set up with [`wake_on_pin_change`], like the buttons,
+//! and the watchdog wake the board from Power-down.
+
+use core::cell::Cell;
+
+use crate::{
+    backend::{self, Mutex},
+    rtc,
+    timers::WAKERS,
+};
+
+#[cfg(target_arch = "avr")]
+pub use hw::wake_on_pin_change;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepMode {
+    /// CPU stopped, all timers and peripherals keep running
+    Idle,
+    /// Like Power-down, but an asynchronously clocked Timer2 keeps running
+    PowerSave,
+    /// Only external interrupts, TWI address match and the watchdog wake up
+    PowerDown,
+}
+
+/// Number of [`StayAwake`] guards that are held
+static STAY_AWAKE: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
+
+/// Keeps the board in Idle while it is held, for wake sources that do not
+/// work in the deeper modes.
+pub struct StayAwake {
+    _private: (),
+}
+
+pub fn stay_awake() -> StayAwake {
+    backend::free(|cs| {
+        let holds = STAY_AWAKE.borrow(cs);
+        holds.set(holds.get() + 1);
+    });
+    StayAwake { _private: () }
+}
+
+impl Drop for StayAwake {
+    fn drop(&mut self) {
+        backend::free(|cs| {
+            let holds = STAY_AWAKE.borrow(cs);
+            holds.set(holds.get() - 1);
+        });
+    }
+}
+
+/// Picks the deepest sleep mode that keeps the timers, the wake sources and
+/// the outputs in use running.
+pub fn select_mode() -> SleepMode {
+    let busy = backend::free(|cs| {
+        WAKERS.borrow(cs).borrow().next_wake_time().is_some() || STAY_AWAKE.borrow(cs).get() > 0
+    });
+    if busy || rtc::is_set() {
+        return SleepMode::Idle;
+    }
+    hw::select_mode()
+}
+
+#[cfg(target_arch = "avr")]
+mod hw {
+    use avr_device::atmega328p::{EXINT, TC1, TC2};
+
+    use super::SleepMode;
+
+    /// Pin change interrupt 0 covers port B
+    const PCIE0: u8 = 1 << 0;
+    const PCIF0: u8 = 1 << 0;
+
+    pub fn select_mode() -> SleepMode {
+        let tc1 = unsafe { &*TC1::ptr() };
+        let tc2 = unsafe { &*TC2::ptr() };
+        let tccr1a = tc1.tccr1a.read();
+        let tccr2a = tc2.tccr2a.read();
+        let outputs_active = !tccr1a.com1a().is_disconnected()
+            || !tccr1a.com1b().is_disconnected()
+            || !tccr2a.com2a().is_disconnected()
+            || !tccr2a.com2b().is_disconnected();
+        if outputs_active {
+            SleepMode::Idle
+        } else if tc2.assr.read().as2().bit_is_set() {
+            SleepMode::PowerSave
+        } else {
+            SleepMode::PowerDown
+        }
+    }
+
+    /// Lets a change on the pins of port B in `mask` wake the board, e.g. on
+    /// buttons that are polled. The tasks still read the pins themselves.
+    pub fn wake_on_pin_change(mask: u8) {
+        avr_device::interrupt::free(|_| {
+            let exint = unsafe { &*EXINT::ptr() };
+            exint.pcmsk0.write(|w| unsafe { w.bits(mask) });
+            exint.pcifr.write(|w| unsafe { w.bits(PCIF0) });
+            exint.pcicr.modify(|r, w| unsafe { w.bits(r.bits() | PCIE0) });
+        });
+    }
+
+    /// Only wakes the executor, which polls the tasks afterwards.
+    #[avr_device::interrupt(atmega328p)]
+    fn PCINT0() {}
+}
+
+#[cfg(not(target_arch = "avr"))]
+mod hw {
+    use super::SleepMode;
+
+    /// There are no peripherals on the host.
+    pub fn select_mode() -> SleepMode {
+        SleepMode::PowerDown
+    }
+}
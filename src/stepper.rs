@@ -0,0 +1,237 @@
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::{
+    futures::{
+        channel::{Receiver, Sender},
+        select::{select, Either},
+        ticker::{MissedTickPolicy, Ticker},
+    },
+    time::{Duration, Instant},
+    watchdog,
+};
+
+/// Generates the step pulses for the motor driver
+pub trait StepOutput {
+    type Error;
+
+    fn set_freq(&mut self, freq: u16) -> Result<(), Self::Error>;
+    fn enable(&mut self);
+    fn disable(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Stepper motor behind a driver with an active-low enable input
+pub struct Stepper<E, D, S>
+where
+    E: OutputPin,
+    D: OutputPin,
+    S: StepOutput,
+{
+    enable: E,
+    direction: D,
+    steps: S,
+}
+
+impl<E, D, S> Stepper<E, D, S>
+where
+    E: OutputPin,
+    D: OutputPin,
+    S: StepOutput,
+{
+    pub fn new(enable: E, direction: D, steps: S) -> Self {
+        let mut stepper = Self {
+            enable,
+            direction,
+            steps,
+        };
+        stepper.stop();
+        stepper
+    }
+
+    pub fn set_freq(&mut self, freq: u16) -> Result<(), S::Error> {
+        self.steps.set_freq(freq)
+    }
+
+    pub fn start(&mut self, direction: Direction) {
+        self.enable.set_low().ok();
+        match direction {
+            Direction::Forward => self.direction.set_high().ok(),
+            Direction::Backward => self.direction.set_low().ok(),
+        };
+        self.steps.enable();
+    }
+
+    pub fn stop(&mut self) {
+        self.enable.set_high().ok();
+        self.steps.disable();
+    }
+}
+
+/// Commands for [`motor_control`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotorCommand {
+    Start(Direction),
+    Stop,
+}
+
+/// Step frequency of the manual control, in Hz
+const MANUAL_FREQ: u16 = 500;
+
+/// Sends [`MotorCommand`]s to run the motor forward while `forward` is pulled
+/// low and backward while `backward` is pulled low. The buttons are checked
+/// every 100 ms, and a command is only sent when they change.
+pub async fn button_control<F, B, const N: usize>(
+    forward: &F,
+    backward: &B,
+    commands: Sender<'_, MotorCommand, N>,
+) where
+    F: InputPin,
+    B: InputPin,
+{
+    let mut ticker = Ticker::every(Duration::from_millis(100)).with_policy(MissedTickPolicy::Skip);
+    let mut last = MotorCommand::Stop;
+    loop {
+        let command = if forward.is_low().unwrap_or(false) {
+            MotorCommand::Start(Direction::Forward)
+        } else if backward.is_low().unwrap_or(false) {
+            MotorCommand::Start(Direction::Backward)
+        } else {
+            MotorCommand::Stop
+        };
+        if command != last {
+            commands.send(command).await;
+            last = command;
+        }
+        ticker.next().await;
+    }
+}
+
+/// Steps made since `since`, estimated from the step frequency
+fn steps_since(since: Instant, direction: Direction) -> i32 {
+    let steps = (since.elapsed().as_millis() as u64 * MANUAL_FREQ as u64 / 1000) as i32;
+    match direction {
+        Direction::Forward => steps,
+        Direction::Backward => -steps,
+    }
+}
+
+/// Runs the motor as commanded and publishes its position in steps every
+/// 100 ms while it moves. Only returns if the step output does not support
+/// the step frequency.
+pub async fn motor_control<E, D, S, const N: usize, const M: usize>(
+    stepper: &mut Stepper<E, D, S>,
+    commands: Receiver<'_, MotorCommand, N>,
+    positions: Sender<'_, i32, M>,
+) -> Result<!, S::Error>
+where
+    E: OutputPin,
+    D: OutputPin,
+    S: StepOutput,
+{
+    stepper.set_freq(MANUAL_FREQ)?;
+    let mut ticker = Ticker::every(Duration::from_millis(100)).with_policy(MissedTickPolicy::Skip);
+    // Position at the start of the current move, if any
+    let mut position: i32 = 0;
+    let mut moving: Option<(Direction, Instant)> = None;
+    loop {
+        match select(commands.recv(), ticker.next()).await {
+            Either::First(command) => {
+                if let Some((direction, since)) = moving.take() {
+                    position += steps_since(since, direction);
+                }
+                match command {
+                    MotorCommand::Start(direction) => {
+                        stepper.start(direction);
+                        moving = Some((direction, Instant::now()));
+                    }
+                    MotorCommand::Stop => stepper.stop(),
+                }
+                let _ = positions.try_send(position);
+            }
+            Either::Second(()) => {
+                // The display only needs the latest position, so a full
+                // channel is not waited for.
+                if let Some((direction, since)) = moving {
+                    let _ = positions.try_send(position + steps_since(since, direction));
+                }
+            }
+        }
+        watchdog::check_in();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::{
+        futures::{channel::Channel, delay::Delay, select::select3},
+        simulation::{block_on, RecordingPin},
+        timers::virtual_clock,
+    };
+
+    #[derive(Default)]
+    struct FakeSteps {
+        freq: u16,
+        enabled: bool,
+    }
+
+    impl StepOutput for FakeSteps {
+        type Error = core::convert::Infallible;
+
+        fn set_freq(&mut self, freq: u16) -> Result<(), Self::Error> {
+            self.freq = freq;
+            Ok(())
+        }
+
+        fn enable(&mut self) {
+            self.enabled = true;
+        }
+
+        fn disable(&mut self) {
+            self.enabled = false;
+        }
+    }
+
+    #[test]
+    fn position_follows_the_moves() {
+        let _clock = virtual_clock::lock_for_test();
+        let commands: Channel<MotorCommand, 2> = Channel::new();
+        let positions: Channel<i32, 2> = Channel::new();
+        let mut stepper = Stepper::new(
+            RecordingPin::default(),
+            RecordingPin::default(),
+            FakeSteps::default(),
+        );
+        let latest = Cell::new(None);
+        let script = async {
+            commands.send(MotorCommand::Start(Direction::Forward)).await;
+            Delay::wait_for(Duration::from_secs(2)).await;
+            commands.send(MotorCommand::Start(Direction::Backward)).await;
+            Delay::wait_for(Duration::from_millis(600)).await;
+            commands.send(MotorCommand::Stop).await;
+            Delay::wait_for(Duration::from_millis(500)).await;
+        };
+        let collect = async {
+            loop {
+                latest.set(Some(positions.recv().await));
+            }
+        };
+        let control = motor_control(&mut stepper, commands.receiver(), positions.sender());
+        block_on(select3(control, script, collect));
+
+        // 2 s forward and 0.6 s backward at 500 Hz
+        assert_eq!(latest.get(), Some(1000 - 300));
+        assert_eq!(stepper.steps.freq, MANUAL_FREQ);
+        assert!(!stepper.steps.enabled);
+        // The enable input is active low.
+        assert_eq!(stepper.enable.changes, [(0, true), (0, false), (2000, false), (2600, true)]);
+        assert_eq!(stepper.direction.changes, [(0, true), (2000, false)]);
+    }
+}
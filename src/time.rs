@@ -0,0 +1,178 @@
+//! Millisecond time types that survive the wraparound of [`millis`].
+//!
+//! `millis` wraps around after about 49.7 days. [`Instant`]s are compared by
+//! their wrapping difference instead, which is correct as long as the two
+//! instants are less than about 24.8 days apart. Timers further out than
+//! that are not supported.
+
+use core::{
+    cmp::Ordering,
+    ops::{Add, AddAssign, Mul, Sub, SubAssign},
+};
+
+use crate::timers::millis;
+
+/// Point in time, in milliseconds since the clock was started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instant {
+    millis: u32,
+}
+
+impl Instant {
+    pub const fn from_millis(millis: u32) -> Self {
+        Self { millis }
+    }
+
+    pub fn now() -> Self {
+        Self::from_millis(millis())
+    }
+
+    pub const fn as_millis(&self) -> u32 {
+        self.millis
+    }
+
+    /// Time passed since `earlier`, zero if `earlier` is actually later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        if *self < earlier {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(self.millis.wrapping_sub(earlier.millis))
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+// Not `Ord`: the comparison is only consistent for instants less than half
+// the range apart, further apart it is not transitive, e.g. for 0, 2^31 - 1
+// and 2^31 + 1 ms. So instants must not be sorted or used with `max`.
+impl PartialOrd for Instant {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some((self.millis.wrapping_sub(other.millis) as i32).cmp(&0))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant::from_millis(self.millis.wrapping_add(rhs.millis))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant::from_millis(self.millis.wrapping_sub(rhs.millis))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Span of time in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Duration {
+    millis: u32,
+}
+
+impl Duration {
+    pub const ZERO: Duration = Duration::from_millis(0);
+
+    pub const fn from_millis(millis: u32) -> Self {
+        Self { millis }
+    }
+
+    pub const fn from_secs(secs: u32) -> Self {
+        Self::from_millis(secs * 1000)
+    }
+
+    pub const fn as_millis(&self) -> u32 {
+        self.millis
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration::from_millis(self.millis.saturating_add(rhs.millis))
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration::from_millis(self.millis.saturating_sub(rhs.millis))
+    }
+}
+
+impl Mul<u32> for Duration {
+    type Output = Duration;
+
+    fn mul(self, rhs: u32) -> Duration {
+        Duration::from_millis(self.millis.saturating_mul(rhs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instants_compare_across_the_wraparound() {
+        let before = Instant::from_millis(u32::MAX - 10);
+        let after = before + Duration::from_millis(20);
+        assert_eq!(after.as_millis(), 9);
+        assert!(after > before);
+        assert!(before < after);
+        assert_eq!(after.duration_since(before), Duration::from_millis(20));
+        assert_eq!(after - before, Duration::from_millis(20));
+        assert_eq!(after - Duration::from_millis(20), before);
+    }
+
+    #[test]
+    fn duration_since_a_later_instant_is_zero() {
+        let earlier = Instant::from_millis(1000);
+        let later = Instant::from_millis(1500);
+        assert_eq!(earlier.duration_since(later), Duration::ZERO);
+    }
+
+    #[test]
+    fn instants_more_than_half_the_range_apart_compare_reversed() {
+        // The documented limit of about 24.8 days
+        let start = Instant::from_millis(0);
+        assert!(start + Duration::from_millis(i32::MAX as u32) > start);
+        assert!(start + Duration::from_millis(i32::MAX as u32 + 1) < start);
+    }
+
+    #[test]
+    fn durations_saturate() {
+        assert_eq!(Duration::from_millis(5) - Duration::from_millis(10), Duration::ZERO);
+        let max = Duration::from_millis(u32::MAX);
+        assert_eq!(max + Duration::from_millis(1), max);
+        assert_eq!(Duration::from_millis(u32::MAX / 2) * 3, max);
+        assert_eq!(Duration::from_secs(3), Duration::from_millis(3000));
+    }
+}
@@ -1,159 +1,155 @@
-use crate::dbgprint;
-use avr_device::interrupt::Mutex;
+use crate::backend::Mutex;
+use crate::time::Instant;
 use core::{
     cell::{Cell, RefCell},
-    cmp::Ordering,
+    marker::PhantomPinned,
+    pin::Pin,
+    ptr,
     task::Waker,
 };
-use heapless::binary_heap::Min;
 
-const FREQ_CPU: u32 = 16_000_000;
-const CLOCK_CYCLES_PER_MICROSECOND: u32 = FREQ_CPU / 1_000_000;
-const PRESCALER: u32 = 64;
+#[cfg(target_arch = "avr")]
+mod timer0;
+#[cfg(target_arch = "avr")]
+pub use timer0::{
+    clock_error_ppb, micros, millis, millis_init, set_clock_error_ppb, ticks, ticks_to_micros,
+    wide_ticks, MAX_CLOCK_ERROR_PPB, TIMER_JITTER_US,
+};
 
-const fn clock_cycles_to_microseconds(cycles: u32) -> u32 {
-    cycles / CLOCK_CYCLES_PER_MICROSECOND
+#[cfg(not(target_arch = "avr"))]
+pub mod virtual_clock;
+#[cfg(not(target_arch = "avr"))]
+pub use virtual_clock::{micros, millis, ticks, ticks_to_micros, TIMER_JITTER_US};
+
+/// A pending timer, linked into [`WAKERS`] in the order of the wake times.
+///
+/// The node lives in the future that waits for it, e.g. a
+/// [`Delay`](crate::futures::delay::Delay), so the number of pending timers
+/// is not limited. Once linked, the node must stay at its address, which
+/// pinning the future guarantees, and it unlinks itself when dropped. All
+/// fields are only accessed in a critical section.
+pub struct TimerNode {
+    wake_time: Cell<Instant>,
+    waker: RefCell<Option<Waker>>,
+    next: Cell<*const TimerNode>,
+    linked: Cell<bool>,
+    _pinned: PhantomPinned,
 }
 
-// the prescaler is set so that timer0 ticks every 64 clock cycles, and the
-// the overflow handler is called every 256 ticks.
-// Should be 1024.
-const MICROSECONDS_PER_TIMER0_OVERFLOW: u32 = clock_cycles_to_microseconds(PRESCALER * 256);
-
-// the whole number of milliseconds per timer0 overflow
-const MILLIS_INC: u32 = MICROSECONDS_PER_TIMER0_OVERFLOW / 1000;
-
-// the fractional number of milliseconds per timer0 overflow. we shift right
-// by three to fit these numbers into a byte. (for the clock speeds we care
-// about - 8 and 16 MHz - this doesn't lose precision.)
-const FRACT_INC: u8 = ((MICROSECONDS_PER_TIMER0_OVERFLOW % 1000) >> 3) as u8;
-const FRACT_MAX: u8 = (1000 >> 3) as u8;
-
-static TIMER0_OVERFLOW_COUNT: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
-static TIMER0_MILLIS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
-
-pub fn millis_init(tc0: &arduino_hal::pac::TC0) {
-    // Configure the timer for the above interval (in CTC mode)
-    // and enable its interrupt.
-    tc0.tccr0a.write(|w| w.wgm0().pwm_fast());
-    // tc0.ocr0a.write(|w| w.bits(TIMER_COUNTS as u8));
-    tc0.tccr0b.write(|w| match PRESCALER {
-        8 => w.cs0().prescale_8(),
-        64 => w.cs0().prescale_64(),
-        256 => w.cs0().prescale_256(),
-        1024 => w.cs0().prescale_1024(),
-        _ => panic!(),
-    });
-    tc0.timsk0.write(|w| w.toie0().set_bit());
-
-    // Reset the global millisecond counter
-    avr_device::interrupt::free(|cs| {
-        TIMER0_MILLIS.borrow(cs).set(0);
-    });
-}
-
-#[avr_device::interrupt(atmega328p)]
-fn TIMER0_OVF() {
-    avr_device::interrupt::free(|cs| {
-        static TIMER0_FRACT: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
-        let mut f = TIMER0_FRACT.borrow(cs).get();
-        let mut m = TIMER0_MILLIS.borrow(cs).get();
-        let overflow_count = TIMER0_OVERFLOW_COUNT.borrow(cs).get();
-
-        m = m.wrapping_add(MILLIS_INC);
-        f = f.wrapping_add(FRACT_INC);
-
-        if f >= FRACT_MAX {
-            f -= FRACT_MAX;
-            m += 1;
+impl TimerNode {
+    pub const fn new() -> Self {
+        Self {
+            wake_time: Cell::new(Instant::from_millis(0)),
+            waker: RefCell::new(None),
+            next: Cell::new(ptr::null()),
+            linked: Cell::new(false),
+            _pinned: PhantomPinned,
         }
-        TIMER0_FRACT.borrow(cs).set(f);
-        TIMER0_MILLIS.borrow(cs).set(m);
-        TIMER0_OVERFLOW_COUNT.borrow(cs).set(overflow_count + 1);
-
-        WAKERS.borrow(cs).borrow_mut().wake_all_before(m);
-    })
-}
-
-#[derive(Debug)]
-pub struct WakersHeapEntry {
-    wake_time: u32,
-    id: u16,
-    waker: Waker,
-}
-
-impl PartialEq for WakersHeapEntry {
-    fn eq(&self, other: &Self) -> bool {
-        self.wake_time == other.wake_time
-    }
-}
-
-impl Eq for WakersHeapEntry {}
-
-impl PartialOrd for WakersHeapEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.wake_time.partial_cmp(&other.wake_time)
     }
 }
 
-impl Ord for WakersHeapEntry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.wake_time.cmp(&other.wake_time)
+impl Drop for TimerNode {
+    fn drop(&mut self) {
+        crate::backend::free(|cs| WAKERS.borrow(cs).borrow_mut().remove(self));
     }
 }
 
-pub struct WakersHeap {
-    heap: heapless::BinaryHeap<WakersHeapEntry, Min, 10>,
+/// Singly linked list of the pending [`TimerNode`]s, the earliest first
+pub struct TimerQueue {
+    head: Cell<*const TimerNode>,
 }
 
-fn wake_time_has_passed(entry: &WakersHeapEntry, now: u32) -> bool {
-    now >= entry.wake_time
-}
+// The nodes are only reached through the queue while the critical section
+// is held.
+unsafe impl Send for TimerQueue {}
 
-impl WakersHeap {
+impl TimerQueue {
     const fn new() -> Self {
         Self {
-            heap: heapless::BinaryHeap::new(),
+            head: Cell::new(ptr::null()),
         }
     }
 
-    pub fn has_capacity(&self) -> bool {
-        self.heap.len() < self.heap.capacity()
-    }
+    /// Links `node` to wake `waker` at `wake_time`, or updates it if it is
+    /// already linked.
+    pub fn insert(&mut self, node: Pin<&TimerNode>, wake_time: Instant, waker: Waker) {
+        let node = node.get_ref();
+        if node.linked.get() {
+            if node.wake_time.get() == wake_time {
+                *node.waker.borrow_mut() = Some(waker);
+                return;
+            }
+            // The position in the list depends on the wake time.
+            self.remove(node);
+        }
+        node.wake_time.set(wake_time);
+        *node.waker.borrow_mut() = Some(waker);
 
-    pub fn replace_or_push(&mut self, wake_time: u32, id: u16, waker: Waker) -> Result<(), ()> {
-        for entry in self.heap.iter_mut() {
-            if entry.id == id {
-                entry.waker = waker;
-                return Ok(());
+        // Behind the timers that are due earlier or at the same time
+        let mut link = &self.head;
+        while let Some(next) = unsafe { link.get().as_ref() } {
+            if next.wake_time.get() > wake_time {
+                break;
             }
+            link = &next.next;
         }
-        match self.heap.push(WakersHeapEntry {
-            wake_time,
-            id,
-            waker,
-        }) {
-            Ok(_) => Ok(()),
-            Err(_) => {
-                dbgprint!("Push NOT Ok!");
-                Err(())
+        node.next.set(link.get());
+        link.set(node);
+        node.linked.set(true);
+    }
+
+    pub fn next_wake_time(&self) -> Option<Instant> {
+        unsafe { self.head.get().as_ref() }.map(|node| node.wake_time.get())
+    }
+
+    /// Unlinks `node` if it is linked, e.g. of a dropped delay.
+    pub fn remove(&mut self, node: &TimerNode) {
+        if node.linked.get() {
+            self.unlink_where(|n| ptr::eq(n, node));
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        self.unlink_where(|_| true);
+    }
+
+    fn unlink_where(&mut self, mut unlink: impl FnMut(&TimerNode) -> bool) {
+        let mut link = &self.head;
+        while let Some(node) = unsafe { link.get().as_ref() } {
+            if unlink(node) {
+                link.set(node.next.get());
+                node.next.set(ptr::null());
+                node.linked.set(false);
+                node.waker.borrow_mut().take();
+            } else {
+                link = &node.next;
             }
         }
     }
 
-    fn wake_all_before(&mut self, now: u32) {
-        while let Some(entry) = self.heap.peek() {
-            if !wake_time_has_passed(entry, now) {
+    fn wake_all_before(&mut self, now: Instant) {
+        while let Some(node) = unsafe { self.head.get().as_ref() } {
+            if now < node.wake_time.get() {
                 break;
             }
-            entry.waker.wake_by_ref();
-            self.heap.pop();
+            self.head.set(node.next.get());
+            node.next.set(ptr::null());
+            node.linked.set(false);
+            if let Some(waker) = node.waker.borrow_mut().take() {
+                waker.wake();
+            }
         }
     }
 }
 
-pub static WAKERS: Mutex<RefCell<WakersHeap>> = Mutex::new(RefCell::new(WakersHeap::new()));
+pub static WAKERS: Mutex<RefCell<TimerQueue>> = Mutex::new(RefCell::new(TimerQueue::new()));
 
-pub fn millis() -> u32 {
-    avr_device::interrupt::free(|cs| TIMER0_MILLIS.borrow(cs).get())
+/// Wakes `waker` at `wake_time`, see [`TimerQueue::insert`].
+pub fn schedule_wakeup(node: Pin<&TimerNode>, wake_time: Instant, waker: Waker) {
+    crate::backend::free(|cs| {
+        WAKERS.borrow(cs).borrow_mut().insert(node, wake_time, waker);
+        #[cfg(all(target_arch = "avr", feature = "tickless"))]
+        timer0::set_compare(cs);
+    });
 }
@@ -0,0 +1,267 @@
+use avr_device::interrupt::Mutex;
+#[cfg(feature = "tickless")]
+use avr_device::interrupt::CriticalSection;
+use core::cell::Cell;
+
+use super::WAKERS;
+use crate::{
+    config::{CPU_FREQUENCY, TIMER0_PRESCALER as PRESCALER},
+    time::Instant,
+};
+
+const CLOCK_CYCLES_PER_MILLISECOND: u32 = CPU_FREQUENCY / 1000;
+
+// the prescaler is set so that timer0 ticks every PRESCALER clock cycles, and
+// the overflow handler is called every 256 ticks.
+const CLOCK_CYCLES_PER_TIMER0_OVERFLOW: u32 = PRESCALER * 256;
+
+const _: () = assert!(
+    matches!(PRESCALER, 8 | 64 | 256 | 1024),
+    "the Timer0 prescaler must be 8, 64, 256 or 1024"
+);
+// Counting in clock cycles keeps `millis` exact for any whole number of kHz.
+const _: () = assert!(
+    CPU_FREQUENCY % 1000 == 0,
+    "the CPU frequency must be a whole number of kHz"
+);
+const _: () = assert!(
+    2 * CLOCK_CYCLES_PER_MILLISECOND <= u16::MAX as u32,
+    "the CPU frequency is too high"
+);
+
+// Whether a tick is a whole number of microseconds, as it is for 8, 16 and
+// 20 MHz with a prescaler of 64.
+const EXACT_MICROS: bool = (PRESCALER * 1000) % CLOCK_CYCLES_PER_MILLISECOND == 0;
+const MICROSECONDS_PER_TICK: u32 = PRESCALER * 1000 / CLOCK_CYCLES_PER_MILLISECOND;
+
+// the whole number of milliseconds per timer0 overflow
+const MILLIS_INC: u32 = CLOCK_CYCLES_PER_TIMER0_OVERFLOW / CLOCK_CYCLES_PER_MILLISECOND;
+
+// the remaining clock cycles per timer0 overflow, which add up to another
+// millisecond every few overflows.
+const FRACT_INC: u16 = (CLOCK_CYCLES_PER_TIMER0_OVERFLOW % CLOCK_CYCLES_PER_MILLISECOND) as u16;
+const FRACT_MAX: u16 = CLOCK_CYCLES_PER_MILLISECOND as u16;
+
+/// How much earlier, measured in [`micros`], a timer in [`WAKERS`] may fire.
+/// `millis` is only updated at every overflow, or at compare matches in
+/// tickless mode, and occasionally skips a value.
+#[cfg(not(feature = "tickless"))]
+pub const TIMER_JITTER_US: u32 = ticks_to_micros(2 * 256) + 1000;
+#[cfg(feature = "tickless")]
+pub const TIMER_JITTER_US: u32 = ticks_to_micros(2) + 1000;
+
+static TIMER0_OVERFLOW_COUNT: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+static TIMER0_MILLIS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+static TIMER0_FRACT: Mutex<Cell<u16>> = Mutex::new(Cell::new(0));
+
+/// Largest clock error that [`set_clock_error_ppb`] corrects, 1 %
+pub const MAX_CLOCK_ERROR_PPB: i32 = 10_000_000;
+
+static CLOCK_ERROR_PPB: Mutex<Cell<i32>> = Mutex::new(Cell::new(0));
+// Clock cycles to add to every overflow, in 1/65536 cycles, and the fraction
+// of a cycle left over from the previous overflows.
+static CORRECTION_STEP: Mutex<Cell<i32>> = Mutex::new(Cell::new(0));
+static CORRECTION_FRACT: Mutex<Cell<u16>> = Mutex::new(Cell::new(0));
+
+pub fn millis_init(tc0: &arduino_hal::pac::TC0) {
+    // Configure the timer for the above interval (in CTC mode)
+    // and enable its interrupt.
+    #[cfg(not(feature = "tickless"))]
+    tc0.tccr0a.write(|w| w.wgm0().pwm_fast());
+    // OCR0A is only updated at the overflow in the PWM modes.
+    #[cfg(feature = "tickless")]
+    tc0.tccr0a.write(|w| w.wgm0().normal_top());
+    // tc0.ocr0a.write(|w| w.bits(TIMER_COUNTS as u8));
+    tc0.tccr0b.write(|w| match PRESCALER {
+        8 => w.cs0().prescale_8(),
+        64 => w.cs0().prescale_64(),
+        256 => w.cs0().prescale_256(),
+        _ => w.cs0().prescale_1024(),
+    });
+    tc0.timsk0.write(|w| w.toie0().set_bit());
+
+    // Reset the global millisecond counter
+    avr_device::interrupt::free(|cs| {
+        TIMER0_MILLIS.borrow(cs).set(0);
+    });
+}
+
+#[avr_device::interrupt(atmega328p)]
+fn TIMER0_OVF() {
+    avr_device::interrupt::free(|cs| {
+        let mut f = TIMER0_FRACT.borrow(cs).get() as i32;
+        let mut m = TIMER0_MILLIS.borrow(cs).get();
+        let overflow_count = TIMER0_OVERFLOW_COUNT.borrow(cs).get();
+
+        let correction = CORRECTION_FRACT.borrow(cs).get() as i32;
+        let correction = correction + CORRECTION_STEP.borrow(cs).get();
+        CORRECTION_FRACT.borrow(cs).set(correction as u16);
+
+        m = m.wrapping_add(MILLIS_INC);
+        f += FRACT_INC as i32 + (correction >> 16);
+
+        // The overflow stays longer than zero after the correction, so `m`
+        // never goes backwards in total.
+        while f >= FRACT_MAX as i32 {
+            f -= FRACT_MAX as i32;
+            m = m.wrapping_add(1);
+        }
+        while f < 0 {
+            f += FRACT_MAX as i32;
+            m = m.wrapping_sub(1);
+        }
+        TIMER0_FRACT.borrow(cs).set(f as u16);
+        TIMER0_MILLIS.borrow(cs).set(m);
+        TIMER0_OVERFLOW_COUNT.borrow(cs).set(overflow_count.wrapping_add(1));
+
+        WAKERS.borrow(cs).borrow_mut().wake_all_before(Instant::from_millis(m));
+        #[cfg(feature = "tickless")]
+        set_compare(cs);
+    })
+}
+
+#[cfg(not(feature = "tickless"))]
+pub fn millis() -> u32 {
+    avr_device::interrupt::free(|cs| TIMER0_MILLIS.borrow(cs).get())
+}
+
+/// The overflows are too far apart in tickless mode, so the current count of
+/// Timer0 is added.
+#[cfg(feature = "tickless")]
+pub fn millis() -> u32 {
+    avr_device::interrupt::free(millis_cs)
+}
+
+#[cfg(feature = "tickless")]
+fn millis_cs(cs: CriticalSection) -> u32 {
+    let tc0 = unsafe { &*arduino_hal::pac::TC0::ptr() };
+    let m = TIMER0_MILLIS.borrow(cs).get();
+    let mut count = tc0.tcnt0.read().bits() as u32;
+    // The overflow interrupt may be pending while interrupts are disabled.
+    if tc0.tifr0.read().tov0().bit_is_set() && count < 255 {
+        count += 256;
+    }
+    let cycles = TIMER0_FRACT.borrow(cs).get() as u32 + ((count * cycles_per_tick_q8(cs)) >> 8);
+    m.wrapping_add(cycles / CLOCK_CYCLES_PER_MILLISECOND)
+}
+
+/// Corrected clock cycles per tick, in 1/256 cycles
+#[cfg(feature = "tickless")]
+fn cycles_per_tick_q8(cs: CriticalSection) -> u32 {
+    ((PRESCALER << 8) as i32 + (CORRECTION_STEP.borrow(cs).get() >> 16)) as u32
+}
+
+/// Programs the compare match for the earliest timer, if it is due before
+/// the next overflow. Otherwise the overflow handler takes care of it.
+#[cfg(feature = "tickless")]
+pub(super) fn set_compare(cs: CriticalSection) {
+    let tc0 = unsafe { &*arduino_hal::pac::TC0::ptr() };
+    tc0.timsk0.modify(|_, w| w.ocie0a().clear_bit());
+    loop {
+        let Some(deadline) = WAKERS.borrow(cs).borrow().next_wake_time() else {
+            return;
+        };
+        let m = TIMER0_MILLIS.borrow(cs).get();
+        let fract = TIMER0_FRACT.borrow(cs).get() as i32;
+        let due_ms = deadline.as_millis().wrapping_sub(m) as i32;
+        if due_ms > MILLIS_INC as i32 + 1 {
+            return;
+        }
+        // Count at which `millis` reaches the deadline, rounded up
+        let due_cycles = due_ms.saturating_mul(CLOCK_CYCLES_PER_MILLISECOND as i32) - fract;
+        let due_cycles = (due_cycles.max(0) as u32) << 8;
+        let cycles_per_tick = cycles_per_tick_q8(cs);
+        let target = (due_cycles + cycles_per_tick - 1) / cycles_per_tick;
+        if target > 255 {
+            return;
+        }
+
+        tc0.ocr0a.write(|w| w.bits(target as u8));
+        tc0.tifr0.write(|w| w.ocf0a().set_bit());
+        tc0.timsk0.modify(|_, w| w.ocie0a().set_bit());
+        // The counter may already have passed the target.
+        if (tc0.tcnt0.read().bits() as u32) < target {
+            return;
+        }
+        wake_due_timers(cs);
+    }
+}
+
+#[cfg(feature = "tickless")]
+fn wake_due_timers(cs: CriticalSection) {
+    let now = Instant::from_millis(millis_cs(cs));
+    WAKERS.borrow(cs).borrow_mut().wake_all_before(now);
+}
+
+#[cfg(feature = "tickless")]
+#[avr_device::interrupt(atmega328p)]
+fn TIMER0_COMPA() {
+    avr_device::interrupt::free(|cs| {
+        wake_due_timers(cs);
+        set_compare(cs);
+    })
+}
+
+/// Sets the error of the CPU clock in parts per billion, positive if it runs
+/// fast, and corrects `millis` for it from now on. `ticks` and `micros` are
+/// not corrected.
+pub fn set_clock_error_ppb(ppb: i32) {
+    let ppb = ppb.clamp(-MAX_CLOCK_ERROR_PPB, MAX_CLOCK_ERROR_PPB);
+    // An overflow takes CLOCK_CYCLES_PER_TIMER0_OVERFLOW / (1 + error) of
+    // the nominal clock cycles.
+    let step = -(CLOCK_CYCLES_PER_TIMER0_OVERFLOW as i64 * 65536 * ppb as i64)
+        / (1_000_000_000 + ppb as i64);
+    avr_device::interrupt::free(|cs| {
+        CLOCK_ERROR_PPB.borrow(cs).set(ppb);
+        CORRECTION_STEP.borrow(cs).set(step as i32);
+    });
+}
+
+pub fn clock_error_ppb() -> i32 {
+    avr_device::interrupt::free(|cs| CLOCK_ERROR_PPB.borrow(cs).get())
+}
+
+/// Number of Timer0 ticks since [`millis_init`]. Wraps around after 2^32
+/// ticks, about 4.8 hours with the default configuration.
+pub fn ticks() -> u32 {
+    wide_ticks() as u32
+}
+
+/// Converts a number of [`ticks`] to microseconds, saturating at `u32::MAX`.
+pub const fn ticks_to_micros(ticks: u32) -> u32 {
+    if EXACT_MICROS {
+        ticks.saturating_mul(MICROSECONDS_PER_TICK)
+    } else {
+        let us = ticks as u64 * (PRESCALER * 1000) as u64 / CLOCK_CYCLES_PER_MILLISECOND as u64;
+        if us > u32::MAX as u64 {
+            u32::MAX
+        } else {
+            us as u32
+        }
+    }
+}
+
+/// Microseconds since [`millis_init`], wrapping around after 2^32 us
+pub fn micros() -> u32 {
+    if EXACT_MICROS {
+        ticks().wrapping_mul(MICROSECONDS_PER_TICK)
+    } else {
+        // Converting the wrapped `ticks` would not wrap at 2^32 us.
+        (wide_ticks() * (PRESCALER * 1000) as u64 / CLOCK_CYCLES_PER_MILLISECOND as u64) as u32
+    }
+}
+
+/// Like [`ticks`], but only wraps around after 2^40 ticks
+pub fn wide_ticks() -> u64 {
+    avr_device::interrupt::free(|cs| {
+        let tc0 = unsafe { &*arduino_hal::pac::TC0::ptr() };
+        let mut overflow_count = TIMER0_OVERFLOW_COUNT.borrow(cs).get();
+        let count = tc0.tcnt0.read().bits();
+
+        // The overflow interrupt may be pending while interrupts are disabled.
+        if tc0.tifr0.read().tov0().bit_is_set() && count < 255 {
+            overflow_count = overflow_count.wrapping_add(1);
+        }
+        ((overflow_count as u64) << 8) | count as u64
+    })
+}
@@ -0,0 +1,82 @@
+//! Virtual millisecond clock standing in for Timer0 on the host.
+//!
+//! Time only moves when it is advanced explicitly or when the executor runs
+//! out of ready tasks, see [`crate::backend::idle`].
+
+use crate::backend::{free, Mutex};
+use core::cell::Cell;
+
+use super::WAKERS;
+use crate::time::Instant;
+
+static MILLIS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Timers fire exactly at their deadline on the host.
+pub const TIMER_JITTER_US: u32 = 0;
+
+pub fn millis() -> u32 {
+    free(|cs| MILLIS.borrow(cs).get())
+}
+
+/// Microseconds of virtual time. Time does not pass while a task is polled.
+pub fn ticks() -> u32 {
+    millis().wrapping_mul(1000)
+}
+
+/// Ticks are microseconds on the host.
+pub const fn ticks_to_micros(ticks: u32) -> u32 {
+    ticks
+}
+
+/// Microseconds since the clock was started, like the Arduino `micros()`.
+/// Wraps around after about 71 minutes.
+pub fn micros() -> u32 {
+    ticks()
+}
+
+/// Moves the clock back to zero and forgets all pending timers.
+#[allow(dead_code)]
+pub fn reset() {
+    free(|cs| {
+        MILLIS.borrow(cs).set(0);
+        WAKERS.borrow(cs).borrow_mut().clear();
+    });
+}
+
+/// Serializes the tests that use the virtual clock, which all threads share,
+/// and starts each of them at zero.
+#[cfg(test)]
+pub fn lock_for_test() -> std::sync::MutexGuard<'static, ()> {
+    static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let guard = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    reset();
+    guard
+}
+
+/// Moves the clock forward by `delay` milliseconds, waking all timers that
+/// expire on the way.
+#[allow(dead_code)]
+pub fn advance(delay: u32) {
+    free(|cs| {
+        let now = MILLIS.borrow(cs).get().wrapping_add(delay);
+        MILLIS.borrow(cs).set(now);
+        WAKERS.borrow(cs).borrow_mut().wake_all_before(Instant::from_millis(now));
+    });
+}
+
+/// Moves the clock forward to the earliest pending timer and wakes it.
+/// Returns `false` if no timer is pending.
+pub fn advance_to_next_deadline() -> bool {
+    free(|cs| {
+        let mut wakers = WAKERS.borrow(cs).borrow_mut();
+        let Some(wake_time) = wakers.next_wake_time() else {
+            return false;
+        };
+        let now = Instant::from_millis(MILLIS.borrow(cs).get());
+        // Timers that are already due do not move the clock back.
+        let now = if now < wake_time { wake_time } else { now };
+        MILLIS.borrow(cs).set(now.as_millis());
+        wakers.wake_all_before(now);
+        true
+    })
+}
@@ -0,0 +1,287 @@
+//! Hardware watchdog tied to the liveness of individual tasks.
+//!
+//! Tasks are registered with [`watch`] and have to call [`check_in`] within
+//! their own timeout, until they complete or are aborted or [`unwatch`]ed. The executor calls [`service`] on every round and only
+//! feeds the watchdog while all watched tasks are on time, so a single stuck
+//! task resets the board.
+//!
+//! The watchdog runs in interrupt + reset mode: the first timeout records the
+//! offending task in RAM that survives the reset, the second one resets. The
+//! record is printed by [`report_last_reset`] on the next boot.
+
+use core::cell::RefCell;
+
+use heapless::Vec;
+
+use crate::{
+    backend::{self, Mutex},
+    executor::{current_task, JoinHandle, TaskId},
+    time::{Duration, Instant},
+};
+
+/// Maximum number of tasks that can be watched
+pub const MAX_WATCHED_TASKS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchError {
+    /// [`MAX_WATCHED_TASKS`] tasks are already watched
+    Full,
+}
+
+struct Watched {
+    task: TaskId,
+    /// Of the task, a later task in the same slot is not watched
+    generation: u16,
+    timeout: Duration,
+    last_check_in: Instant,
+}
+
+impl Watched {
+    fn is_overdue(&self, now: Instant) -> bool {
+        now.duration_since(self.last_check_in) > self.timeout
+    }
+}
+
+static WATCHED: Mutex<RefCell<Vec<Watched, MAX_WATCHED_TASKS>>> = Mutex::new(RefCell::new(Vec::new()));
+
+/// Starts watching `task`, which has to [`check_in`] at least every
+/// `timeout` from now on. Does nothing if the task has already finished.
+pub fn watch(task: &JoinHandle<'_>, timeout: Duration) -> Result<(), WatchError> {
+    let now = Instant::now();
+    backend::free(|cs| {
+        // Its slot may be running another task already.
+        if task.is_finished() {
+            return Ok(());
+        }
+        let mut watched = WATCHED.borrow(cs).borrow_mut();
+        watched.retain(|w| w.task != task.id());
+        watched
+            .push(Watched {
+                task: task.id(),
+                generation: task.generation(),
+                timeout,
+                last_check_in: now,
+            })
+            .map_err(|_| WatchError::Full)
+    })
+}
+
+/// Stops watching `task`.
+#[allow(dead_code)]
+pub fn unwatch(task: &JoinHandle<'_>) {
+    remove(task.id(), task.generation());
+}
+
+/// Stops watching the task `generation` in the slot of `task`. Called by the
+/// executor when the task completes or is aborted.
+pub fn remove(task: TaskId, generation: u16) {
+    backend::free(|cs| {
+        let mut watched = WATCHED.borrow(cs).borrow_mut();
+        watched.retain(|w| w.task != task || w.generation != generation);
+    });
+}
+
+/// Signals that the current task is still alive. Does nothing if the task is
+/// not watched.
+pub fn check_in() {
+    let Some(task) = current_task() else {
+        return;
+    };
+    let now = Instant::now();
+    backend::free(|cs| {
+        let mut watched = WATCHED.borrow(cs).borrow_mut();
+        if let Some(w) = watched.iter_mut().find(|w| w.task == task) {
+            w.last_check_in = now;
+        }
+    });
+}
+
+/// First watched task that missed its deadline
+fn overdue_task() -> Option<TaskId> {
+    let now = Instant::now();
+    backend::free(|cs| {
+        let watched = WATCHED.borrow(cs).borrow();
+        watched.iter().find(|w| w.is_overdue(now)).map(|w| w.task)
+    })
+}
+
+/// Feeds the watchdog if all watched tasks checked in on time. Called by the
+/// executor.
+pub fn service() {
+    if overdue_task().is_none() {
+        hw::feed();
+    }
+}
+
+/// Prints the task that caused the last watchdog reset, if any.
+pub fn report_last_reset() {
+    hw::report_last_reset();
+}
+
+#[cfg(target_arch = "avr")]
+pub use hw::start;
+
+#[cfg(target_arch = "avr")]
+mod hw {
+    use core::{
+        mem::MaybeUninit,
+        ptr,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use avr_device::atmega328p::{wdt::RegisterBlock, CPU, WDT};
+
+    use super::overdue_task;
+    use crate::{dbgprint, executor::current_task};
+
+    const WDIE: u8 = 1 << 6;
+    const WDCE: u8 = 1 << 4;
+    const WDE: u8 = 1 << 3;
+    /// 2 s timeout, WDP3 = 0, WDP2:0 = 0b111
+    const WDP_2S: u8 = 0b111;
+
+    /// Marks [`RESET_RECORD`] as valid
+    const RECORD_MAGIC: u16 = 0xd06e;
+    /// Recorded if neither a task was running nor one was overdue
+    const UNKNOWN_TASK: u8 = 0xff;
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    struct ResetRecord {
+        magic: u16,
+        task: u8,
+    }
+
+    // Not initialized by the startup code, so it survives the watchdog reset.
+    #[link_section = ".noinit"]
+    static mut RESET_RECORD: MaybeUninit<ResetRecord> = MaybeUninit::uninit();
+
+    /// Started by [`start`], the watchdog is not fed before that
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    /// The interrupt fired and has to be enabled again once the tasks recover
+    static FIRED: AtomicBool = AtomicBool::new(false);
+
+    fn wdt() -> &'static RegisterBlock {
+        unsafe { &*WDT::ptr() }
+    }
+
+    fn write_record(record: ResetRecord) {
+        unsafe { ptr::write_volatile(ptr::addr_of_mut!(RESET_RECORD) as *mut ResetRecord, record) };
+    }
+
+    /// Reads and invalidates the record.
+    fn take_record() -> Option<u8> {
+        let record = unsafe { ptr::read_volatile(ptr::addr_of!(RESET_RECORD) as *const ResetRecord) };
+        write_record(ResetRecord {
+            magic: 0,
+            task: UNKNOWN_TASK,
+        });
+        (record.magic == RECORD_MAGIC).then_some(record.task)
+    }
+
+    /// Starts the watchdog with a 2 s timeout in interrupt + reset mode.
+    pub fn start(wdt: &WDT, cpu: &CPU) {
+        avr_device::interrupt::free(|_| {
+            avr_device::asm::wdr();
+            // WDE cannot be changed while the reset flag is set.
+            cpu.mcusr.modify(|_, w| w.wdrf().clear_bit());
+            // Timed sequence, the second write has to follow within 4 cycles.
+            wdt.wdtcsr.write(|w| unsafe { w.bits(WDCE | WDE) });
+            wdt.wdtcsr.write(|w| unsafe { w.bits(WDIE | WDE | WDP_2S) });
+            STARTED.store(true, Ordering::Release);
+        });
+    }
+
+    pub fn feed() {
+        if !STARTED.load(Ordering::Acquire) {
+            return;
+        }
+        avr_device::asm::wdr();
+        if FIRED.load(Ordering::Acquire) {
+            // The tasks recovered before the reset, forget about the hang.
+            avr_device::interrupt::free(|_| {
+                wdt().wdtcsr.modify(|_, w| w.wdie().set_bit());
+                FIRED.store(false, Ordering::Release);
+                take_record();
+            });
+        }
+    }
+
+    pub fn report_last_reset() {
+        match take_record() {
+            Some(UNKNOWN_TASK) => dbgprint!("Watchdog reset, no task was overdue"),
+            Some(task) => dbgprint!("Watchdog reset, task {} was stuck", task),
+            None => {}
+        }
+    }
+
+    /// Fires on the first watchdog timeout, the next one resets the board.
+    #[avr_device::interrupt(atmega328p)]
+    fn WDT() {
+        // A healthy task may be polled while another one is late. Without an
+        // overdue task, the one stuck inside `poll` is the current one.
+        let task = overdue_task()
+            .or_else(current_task)
+            .map_or(UNKNOWN_TASK, |task| task.0);
+        write_record(ResetRecord {
+            magic: RECORD_MAGIC,
+            task,
+        });
+        FIRED.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(not(target_arch = "avr"))]
+mod hw {
+    /// There is no watchdog on the host.
+    pub fn feed() {}
+
+    pub fn report_last_reset() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+
+    use super::*;
+    use crate::{executor::Executor, futures::delay::Delay, timers::virtual_clock};
+
+    fn watched_tasks() -> std::vec::Vec<(TaskId, u16)> {
+        backend::free(|cs| {
+            let watched = WATCHED.borrow(cs).borrow();
+            watched.iter().map(|w| (w.task, w.generation)).collect()
+        })
+    }
+
+    #[test]
+    fn tasks_are_watched_until_they_finish() {
+        let _clock = virtual_clock::lock_for_test();
+        let executor = pin!(Executor::<1>::new());
+        let executor = executor.into_ref();
+        let timeout = Duration::from_millis(100);
+
+        let completed = executor.spawn(Delay::wait_for(Duration::from_millis(10))).unwrap();
+        watch(&completed, timeout).unwrap();
+        assert_eq!(watched_tasks(), [(completed.id(), completed.generation())]);
+        executor.run();
+        assert_eq!(watched_tasks(), []);
+
+        // The next task in the same slot does not inherit the old entry.
+        let aborted = executor.spawn(Delay::wait_for(Duration::from_secs(1))).unwrap();
+        assert_eq!(aborted.id(), completed.id());
+        watch(&completed, timeout).unwrap();
+        assert_eq!(watched_tasks(), []);
+
+        watch(&aborted, timeout).unwrap();
+        aborted.abort();
+        executor.run();
+        assert_eq!(watched_tasks(), []);
+        assert!(overdue_task().is_none());
+
+        let unwatched = executor.spawn(Delay::wait_for(Duration::from_secs(1))).unwrap();
+        watch(&unwatched, timeout).unwrap();
+        unwatch(&unwatched);
+        assert_eq!(watched_tasks(), []);
+        executor.run();
+    }
+}